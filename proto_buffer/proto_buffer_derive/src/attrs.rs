@@ -0,0 +1,285 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+
+#[derive(Default)]
+pub struct FieldAttrs {
+    pub field: Option<(u32, proc_macro2::Span)>,
+    pub id: Option<(u32, proc_macro2::Span)>,
+    pub skip: bool,
+    pub default: Option<syn::Path>,
+    pub with: Option<syn::Path>,
+}
+
+impl FieldAttrs {
+    pub fn parse(attrs: &[syn::Attribute]) -> syn::Result<FieldAttrs> {
+        let mut res = FieldAttrs::default();
+
+        for meta in proto_metas(attrs)? {
+            match &meta {
+                syn::Meta::NameValue(nv) if nv.path.is_ident("field") => {
+                    res.field = Some((int_lit(&nv.lit)?, nv.lit.span()));
+                }
+                syn::Meta::NameValue(nv) if nv.path.is_ident("id") => {
+                    res.id = Some((int_lit(&nv.lit)?, nv.lit.span()));
+                }
+                syn::Meta::Path(p) if p.is_ident("skip") => {
+                    res.skip = true;
+                }
+                syn::Meta::NameValue(nv) if nv.path.is_ident("default") => {
+                    res.default = Some(path_lit(&nv.lit)?);
+                }
+                syn::Meta::NameValue(nv) if nv.path.is_ident("with") => {
+                    res.with = Some(path_lit(&nv.lit)?);
+                }
+                _ => return Err(syn::Error::new_spanned(meta, "unknown proto attribute"))
+            }
+        }
+
+        match (&res.with, res.field) {
+            (Some(with), _) if res.skip => {
+                return Err(syn::Error::new_spanned(with, "a skipped field can not have `with`"));
+            }
+            (_, Some((_, span))) if res.skip => {
+                return Err(syn::Error::new(span, "a skipped field can not have a field number"));
+            }
+            (Some(with), Some(_)) => {
+                return Err(syn::Error::new_spanned(with, "`with` can not be combined with a field number"));
+            }
+            _ => {}
+        }
+
+        match (res.id, res.field) {
+            (Some((_, span)), _) if res.skip => {
+                return Err(syn::Error::new(span, "a skipped field can not have an id"));
+            }
+            (Some((_, span)), Some(_)) => {
+                return Err(syn::Error::new(span, "`id` can not be combined with a field number"));
+            }
+            _ => {}
+        }
+
+        Ok(res)
+    }
+
+    pub fn default_value(&self, ty: &syn::Type) -> TokenStream2 {
+        match &self.default {
+            Some(path) => quote!(#path()),
+            None => quote!(<#ty as Default>::default())
+        }
+    }
+}
+
+// where a set of fields sits, some field attributes only make sense in some of these places
+#[derive(PartialEq, Clone, Copy)]
+pub enum FieldsKind {
+    Positional,
+    Numbered,
+    Evolvable,
+    Variant,
+}
+
+pub fn check_fields(fields: &syn::Fields, kind: FieldsKind) -> syn::Result<()> {
+    for f in fields.iter() {
+        let attrs = FieldAttrs::parse(&f.attrs)?;
+
+        match (attrs.field, attrs.id) {
+            (Some((_, span)), _) if kind == FieldsKind::Variant => {
+                return Err(syn::Error::new(span, "field numbers are supported on struct fields only"));
+            }
+            (_, Some((_, span))) if kind != FieldsKind::Evolvable => {
+                return Err(syn::Error::new(span, "#[proto(id = N)] needs #[proto(evolvable)]"));
+            }
+            _ => {}
+        }
+
+        // only skipped fields and fields missing from a message are ever defaulted
+        match &attrs.default {
+            Some(default) if !attrs.skip && (kind == FieldsKind::Positional || kind == FieldsKind::Variant) => {
+                return Err(syn::Error::new_spanned(default, "`default` needs `skip` on a field that is always written"));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct VariantAttrs {
+    pub tag: Option<(u64, proc_macro2::Span)>,
+    pub other: bool,
+}
+
+impl VariantAttrs {
+    pub fn parse(variant: &syn::Variant) -> syn::Result<VariantAttrs> {
+        let mut res = VariantAttrs::default();
+
+        for meta in proto_metas(&variant.attrs)? {
+            match &meta {
+                syn::Meta::NameValue(nv) if nv.path.is_ident("tag") => {
+                    res.tag = Some((int_lit(&nv.lit)?, nv.lit.span()));
+                }
+                syn::Meta::Path(p) if p.is_ident("other") => {
+                    res.other = true;
+                }
+                _ => return Err(syn::Error::new_spanned(meta, "unknown proto attribute"))
+            }
+        }
+
+        // #[proto(tag = N)] wins over the Rust discriminant
+        if let (None, Some((_, expr))) = (res.tag, &variant.discriminant) {
+            match expr {
+                syn::Expr::Lit(syn::ExprLit { lit, .. }) => res.tag = Some((int_lit(lit)?, lit.span())),
+                _ => return Err(syn::Error::new_spanned(expr, "expected integer literal discriminant"))
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+#[derive(Default)]
+pub struct ContainerAttrs {
+    pub write_bound: Option<Vec<syn::WherePredicate>>,
+    pub read_bound: Option<Vec<syn::WherePredicate>>,
+    pub tag_type: Option<syn::Ident>,
+    pub length_prefixed: bool,
+    pub evolvable: bool,
+    pub version: Option<u32>,
+    pub min_version: Option<(u32, proc_macro2::Span)>,
+    pub migrate: Option<syn::Path>,
+}
+
+impl ContainerAttrs {
+    pub fn parse(ast: &syn::DeriveInput) -> syn::Result<ContainerAttrs> {
+        let mut res = ContainerAttrs::default();
+        let is_enum = matches!(ast.data, syn::Data::Enum(_));
+
+        for meta in proto_metas(&ast.attrs)? {
+            match &meta {
+                syn::Meta::Path(p) if !is_enum && p.is_ident("length_prefixed") => {
+                    return Err(syn::Error::new_spanned(meta, "`length_prefixed` is supported on enums only"));
+                }
+                syn::Meta::NameValue(nv) if !is_enum && nv.path.is_ident("tag_type") => {
+                    return Err(syn::Error::new_spanned(meta, "`tag_type` is supported on enums only"));
+                }
+                syn::Meta::Path(p) if is_enum && p.is_ident("evolvable") => {
+                    return Err(syn::Error::new_spanned(meta, "`evolvable` is supported on structs only"));
+                }
+                _ => {}
+            }
+
+            match &meta {
+                syn::Meta::NameValue(nv) if nv.path.is_ident("bound") => {
+                    let bound = where_predicates(&nv.lit)?;
+                    res.write_bound = Some(bound.clone());
+                    res.read_bound = Some(bound);
+                }
+                syn::Meta::List(list) if list.path.is_ident("bound") => {
+                    for nested in list.nested.iter() {
+                        match nested {
+                            syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("write") => {
+                                res.write_bound = Some(where_predicates(&nv.lit)?);
+                            }
+                            syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("read") => {
+                                res.read_bound = Some(where_predicates(&nv.lit)?);
+                            }
+                            _ => return Err(syn::Error::new_spanned(nested, "expected `write = \"...\"` or `read = \"...\"`"))
+                        }
+                    }
+                }
+                syn::Meta::NameValue(nv) if nv.path.is_ident("tag_type") => {
+                    let ty: syn::Ident = path_lit(&nv.lit)?.get_ident().cloned()
+                        .ok_or_else(|| syn::Error::new_spanned(&nv.lit, "expected one of u8, u16, u32, u64"))?;
+
+                    if !["u8", "u16", "u32", "u64"].iter().any(|t| ty == t) {
+                        return Err(syn::Error::new_spanned(&nv.lit, "expected one of u8, u16, u32, u64"));
+                    }
+
+                    res.tag_type = Some(ty);
+                }
+                syn::Meta::Path(p) if p.is_ident("length_prefixed") => {
+                    res.length_prefixed = true;
+                }
+                syn::Meta::Path(p) if p.is_ident("evolvable") => {
+                    res.evolvable = true;
+                }
+                syn::Meta::NameValue(nv) if nv.path.is_ident("version") => {
+                    let version = int_lit(&nv.lit)?;
+
+                    if version == 0 {
+                        return Err(syn::Error::new_spanned(&nv.lit, "versions start at 1"));
+                    }
+
+                    res.version = Some(version);
+                }
+                syn::Meta::NameValue(nv) if nv.path.is_ident("min_version") => {
+                    res.min_version = Some((int_lit(&nv.lit)?, nv.lit.span()));
+                }
+                syn::Meta::NameValue(nv) if nv.path.is_ident("migrate") => {
+                    res.migrate = Some(path_lit(&nv.lit)?);
+                }
+                _ => return Err(syn::Error::new_spanned(meta, "unknown proto attribute"))
+            }
+        }
+
+        match (res.version, res.min_version) {
+            (None, Some((_, span))) => return Err(syn::Error::new(span, "min_version needs #[proto(version = N)]")),
+            (Some(version), Some((min, span))) if min == 0 || min > version => {
+                return Err(syn::Error::new(span, format!("min_version must be in 1..={}", version)));
+            }
+            _ => {}
+        }
+
+        Ok(res)
+    }
+}
+
+fn proto_metas(attrs: &[syn::Attribute]) -> syn::Result<Vec<syn::Meta>> {
+    let mut metas = Vec::new();
+
+    for attr in attrs.iter().filter(|a| a.path.is_ident("proto")) {
+        match attr.parse_meta()? {
+            syn::Meta::List(list) => {
+                for nested in list.nested {
+                    match nested {
+                        syn::NestedMeta::Meta(m) => metas.push(m),
+                        syn::NestedMeta::Lit(l) => {
+                            return Err(syn::Error::new_spanned(l, "expected `name` or `name = value`"))
+                        }
+                    }
+                }
+            }
+            m => return Err(syn::Error::new_spanned(m, "expected #[proto(...)]"))
+        }
+    }
+
+    Ok(metas)
+}
+
+fn where_predicates(lit: &syn::Lit) -> syn::Result<Vec<syn::WherePredicate>> {
+    match lit {
+        syn::Lit::Str(s) if s.value().trim().is_empty() => Ok(Vec::new()),
+        syn::Lit::Str(s) => {
+            let clause: syn::WhereClause = syn::parse_str(&format!("where {}", s.value()))
+                .map_err(|e| syn::Error::new_spanned(lit, e))?;
+
+            Ok(clause.predicates.into_iter().collect())
+        }
+        _ => Err(syn::Error::new_spanned(lit, "expected string literal"))
+    }
+}
+
+fn path_lit(lit: &syn::Lit) -> syn::Result<syn::Path> {
+    match lit {
+        syn::Lit::Str(s) => s.parse(),
+        _ => Err(syn::Error::new_spanned(lit, "expected string literal"))
+    }
+}
+
+fn int_lit<T: std::str::FromStr>(lit: &syn::Lit) -> syn::Result<T> where T::Err: std::fmt::Display {
+    match lit {
+        syn::Lit::Int(i) => i.base10_parse(),
+        _ => Err(syn::Error::new_spanned(lit, "expected integer literal"))
+    }
+}
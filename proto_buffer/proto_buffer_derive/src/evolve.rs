@@ -0,0 +1,115 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, format_ident};
+
+use crate::attrs::FieldAttrs;
+use crate::protobuf::NumberedField;
+
+// `id` is None for skipped fields
+pub struct EvolvableField<'a> {
+    pub field: &'a syn::Field,
+    pub member: syn::Member,
+    pub id: Option<u32>,
+    pub attrs: FieldAttrs,
+}
+
+// ids come from #[proto(id = N)], or from #[proto(field = N)] when the struct is a protobuf message,
+// otherwise from the declaration order starting at 1
+pub fn evolvable_fields<'a>(fields: &'a syn::Fields, numbered: Option<&[NumberedField]>) -> syn::Result<Vec<EvolvableField<'a>>> {
+    let mut res: Vec<EvolvableField> = Vec::new();
+    let parsed = fields.iter().map(|f| FieldAttrs::parse(&f.attrs)).collect::<syn::Result<Vec<_>>>()?;
+    let explicit = parsed.iter().any(|a| a.id.is_some());
+
+    for ((pos, f), attrs) in fields.iter().enumerate().zip(parsed) {
+        let id = match (numbered, attrs.id) {
+            (_, Some((id, span))) => {
+                if id == 0 {
+                    return Err(syn::Error::new(span, "ids start at 1"));
+                }
+
+                if res.iter().any(|e| e.id == Some(id)) {
+                    return Err(syn::Error::new(span, format!("duplicate id {}", id)));
+                }
+
+                Some(id)
+            }
+            _ if attrs.skip => None,
+            _ if explicit => return Err(syn::Error::new_spanned(f, "every field needs #[proto(id = N)] once one field has it")),
+            (Some(numbered), None) => numbered[pos].number,
+            (None, None) => Some(pos as u32 + 1)
+        };
+
+        res.push(EvolvableField { field: f, member: crate::struct_field_member(pos, f), id, attrs });
+    }
+
+    Ok(res)
+}
+
+pub fn evolvable_writer(fields: &[EvolvableField]) -> syn::Result<TokenStream2> {
+    let mut writers = quote!();
+
+    for EvolvableField { field, member, id, .. } in fields {
+        if let Some(id) = id {
+            let writer = crate::writer_by_field_ty(field, quote!(&self.#member))?;
+            writers.extend(quote!(
+                buf.try_write_field(#id, |buf| { #writer Ok(()) })?;
+            ));
+        }
+    }
+
+    let param = if writers.is_empty() { quote!(_) } else { quote!(buf) };
+
+    Ok(quote! {
+        buf.try_write_len_prefixed(|#param| { #writers Ok(()) })?;
+    })
+}
+
+// unknown ids are skipped, missing fields get their default
+pub fn evolvable_reader(name: &syn::Ident, fields: &syn::Fields, evolvable: &[EvolvableField], mode: crate::ReadMode) -> syn::Result<TokenStream2> {
+    let mut vars = quote!();
+    let mut arms = quote!();
+    let mut values = Vec::new();
+
+    for (pos, EvolvableField { field, id, attrs, .. }) in evolvable.iter().enumerate() {
+        let ty = &field.ty;
+        let default = attrs.default_value(ty);
+
+        let id = match id {
+            Some(id) => id,
+            None => {
+                values.push(default);
+                continue;
+            }
+        };
+
+        let var = format_ident!("field_{}", pos);
+        let reader = crate::reader_by_field_ty(field, mode)?;
+
+        vars.extend(quote!(
+            let mut #var: Option<#ty> = None;
+        ));
+        arms.extend(quote!(
+            #id => #var = Some(proto_buffer::ProtoSource::try_read_len_prefixed(buf, |buf| {
+                let v = #reader;
+                Ok(v)
+            })?),
+        ));
+        values.push(quote!(#var.unwrap_or_else(|| #default)));
+    }
+
+    let constructor = crate::fields_constructor(fields, &values);
+
+    Ok(quote! {
+        #vars
+
+        proto_buffer::ProtoSource::try_read_fields(buf, |id, buf| {
+            match id {
+                #arms
+                _ => proto_buffer::ProtoSource::try_skip_len_prefixed(buf)?
+            }
+
+            Ok(())
+        })?;
+
+        Ok(#name #constructor)
+    })
+}
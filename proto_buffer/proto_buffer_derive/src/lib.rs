@@ -2,14 +2,60 @@ extern crate proc_macro;
 
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
-use quote::quote;
-use syn;
+use quote::{quote, quote_spanned, format_ident};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+
+mod attrs;
+mod evolve;
+mod protobuf;
+
+// derived readers decode either from any ProtoSource or, without copying, from a BufferView<'de>
+#[derive(Clone, Copy, PartialEq)]
+enum ReadMode {
+    Owned,
+    Borrowed
+}
 
-fn enum_liter_by_pos(pos:&usize, len:&usize) -> proc_macro2::Literal {
-    match *len {
-        n if n <= u8::MAX as usize => { proc_macro2::Literal::u8_suffixed(*pos as u8) }
-        n if n <= u16::MAX as usize => { proc_macro2::Literal::u16_suffixed(*pos as u16) }
-        _ => { proc_macro2::Literal::usize_suffixed(*pos) }
+impl ReadMode {
+    fn reader_trait(self) -> TokenStream2 {
+        match self {
+            ReadMode::Owned => quote!(proto_buffer::ProtoReader),
+            ReadMode::Borrowed => quote!(proto_buffer::ProtoBorrowRead<'de>)
+        }
+    }
+
+    fn read_fn(self) -> TokenStream2 {
+        match self {
+            ReadMode::Owned => quote!(try_proto_read),
+            ReadMode::Borrowed => quote!(try_borrow_read)
+        }
+    }
+
+    // the generic parameter is mangled so it can not clash with the type's own
+    fn read_params(self) -> TokenStream2 {
+        match self {
+            ReadMode::Owned => quote!(<__S: proto_buffer::ProtoSource>(buf:&mut __S)),
+            ReadMode::Borrowed => quote!((buf:&mut proto_buffer::BufferView<'de>))
+        }
+    }
+
+    // the function a #[proto(with = "module")] field is read with
+    fn with_fn(self) -> TokenStream2 {
+        match self {
+            ReadMode::Owned => quote!(read),
+            ReadMode::Borrowed => quote!(borrow_read)
+        }
+    }
+}
+
+fn enum_liter_by_tag(tag:u64, ty:&proc_macro2::Ident) -> Option<proc_macro2::Literal> {
+    match ty.to_string().as_str() {
+        "u8" if tag <= u8::MAX as u64 => { Some(proc_macro2::Literal::u8_suffixed(tag as u8)) }
+        "u16" if tag <= u16::MAX as u64 => { Some(proc_macro2::Literal::u16_suffixed(tag as u16)) }
+        "u32" if tag <= u32::MAX as u64 => { Some(proc_macro2::Literal::u32_suffixed(tag as u32)) }
+        "u64" => { Some(proc_macro2::Literal::u64_suffixed(tag)) }
+        _ => None
     }
 }
 
@@ -17,260 +63,584 @@ fn enum_ident_by_variants_len(len:&usize) -> proc_macro2::Ident {
     match *len {
         n if n <= u8::MAX as usize => { proc_macro2::Ident::new("u8", proc_macro2::Span::call_site()) }
         n if n <= u16::MAX as usize => { proc_macro2::Ident::new("u16", proc_macro2::Span::call_site()) }
-        _ => { proc_macro2::Ident::new("usize", proc_macro2::Span::call_site()) }
+        _ => { proc_macro2::Ident::new("u32", proc_macro2::Span::call_site()) }
     }
 }
 
-fn writer_by_field_ty(f:&syn::Field, is_enum: bool) -> TokenStream2 {
-    match &f.ty {
-        syn::Type::Path(tp) if f.ident.is_some() && !is_enum  => {
-            if tp.path.segments.len() == 1 {
-                match &tp.path.segments.first().unwrap().ident.to_string()[..] {
-                    "String" | "u8" | "i8" | "u16" | "i16" | "u32" | "i32" | "u64" | "i64" | "usize" |
-                    "f64" | "f32" | "bool" | "()" => {
-                        let field_name = f.ident.as_ref().unwrap();
-                        return quote!(
-                            self.#field_name.proto_write(buf);
-                        );
-                    }
-                    v => {
-                        unimplemented!("for {} {}", v, f.ident.as_ref().unwrap().to_string())
-                    }
-                }
-            } else { unimplemented!("for none ident") }
+// tags follow the Rust rules: #[proto(tag = N)] or `= N` when given, otherwise the previous tag + 1.
+// without #[proto(tag_type = "...")] the tag type is picked from the variant count alone, a larger tag
+// has to name its type so it can not silently widen the tags of the other variants
+fn enum_tags(container:&attrs::ContainerAttrs, variants:&Punctuated<syn::Variant, syn::token::Comma>) -> syn::Result<(proc_macro2::Ident, Vec<proc_macro2::Literal>)> {
+    let mut tags: Vec<(u64, proc_macro2::Span)> = Vec::new();
+    let mut next = 0u64;
+
+    for v in variants.iter() {
+        let (tag, span) = match attrs::VariantAttrs::parse(v)?.tag {
+            Some(tag) => tag,
+            None => (next, v.ident.span())
+        };
+
+        if let Some((_, first)) = tags.iter().find(|(t, _)| *t == tag) {
+            let mut err = syn::Error::new(span, format!("duplicate tag {}", tag));
+            err.combine(syn::Error::new(*first, "first used here"));
+            return Err(err);
         }
 
-        syn::Type::Path(tp) if is_enum => {
-            if tp.path.segments.len() == 1 {
-                let first_sement = tp.path.segments.first().unwrap();
-                let pidend = &first_sement.ident;
-                match &pidend.to_string()[..] {
-                    "String" | "u8" | "i8" | "u16" | "i16" | "u32" | "i32" | "u64" | "i64" | "usize" |
-                    "f64" | "f32" | "bool" | "()" => {
-                        return quote!(
-                            v.proto_write(buf);
-                        );
-                    }
-                    v => {
-                        unimplemented!("for {} {}", v, f.ident.as_ref().unwrap().to_string())
-                    }
-                }
-            } else { unimplemented!("for none ident") }
+        tags.push((tag, span));
+        next = tag.wrapping_add(1);
+    }
+
+    let eliter_ty = match &container.tag_type {
+        Some(ty) => ty.clone(),
+        None => enum_ident_by_variants_len(&variants.len())
+    };
+
+    let eliters = tags.iter().map(|(tag, span)| {
+        enum_liter_by_tag(*tag, &eliter_ty).ok_or_else(|| match container.tag_type {
+            Some(_) => syn::Error::new(*span, format!("tag {} does not fit into {}", tag, eliter_ty)),
+            None => syn::Error::new(*span, format!("tag {} does not fit into {}, set #[proto(tag_type = \"...\")]", tag, eliter_ty))
+        })
+    }).collect::<syn::Result<Vec<_>>>()?;
+
+    Ok((eliter_ty, eliters))
+}
+
+fn fields_kind(container:&attrs::ContainerAttrs, numbered:&Option<Vec<protobuf::NumberedField>>) -> attrs::FieldsKind {
+    match (container.evolvable, numbered) {
+        (true, _) => attrs::FieldsKind::Evolvable,
+        (false, Some(_)) => attrs::FieldsKind::Numbered,
+        (false, None) => attrs::FieldsKind::Positional
+    }
+}
+
+// adds `T: bound` for every type parameter, unless #[proto(bound = "...")] replaces them
+fn bounded_generics(generics:&syn::Generics, bound: TokenStream2, custom:&Option<Vec<syn::WherePredicate>>) -> syn::Generics {
+    let mut generics = generics.clone();
+
+    let predicates: Vec<syn::WherePredicate> = match custom {
+        Some(predicates) => predicates.clone(),
+        None => generics.type_params().map(|tp| {
+            let ident = &tp.ident;
+            syn::parse_quote!(#ident: #bound)
+        }).collect()
+    };
+
+    generics.make_where_clause().predicates.extend(predicates);
+    generics
+}
+
+fn writer_by_field_ty(f:&syn::Field, value: TokenStream2) -> syn::Result<TokenStream2> {
+    let ty = &f.ty;
+    let field_attrs = attrs::FieldAttrs::parse(&f.attrs)?;
+
+    if field_attrs.skip {
+        return Ok(quote!());
+    }
+
+    if let Some(with) = &field_attrs.with {
+        return Ok(quote_spanned!(with.span()=>
+            #with::write(#value, buf)?;
+        ));
+    }
+
+    Ok(quote_spanned!(ty.span()=>
+        <#ty as proto_buffer::ProtoWriter>::try_proto_write(#value, buf)?;
+    ))
+}
+
+fn struct_field_member(pos: usize, f:&syn::Field) -> syn::Member {
+    match &f.ident {
+        Some(field_name) => syn::Member::Named(field_name.clone()),
+        None => syn::Member::Unnamed(syn::Index::from(pos))
+    }
+}
+
+// binds the fields of a variant to v0, v1, ... in declaration order
+fn variant_pattern(fields:&syn::Fields) -> (TokenStream2, Vec<syn::Ident>) {
+    let bindings: Vec<_> = (0..fields.len()).map(|i| format_ident!("v{}", i)).collect();
+
+    let pattern = match fields {
+        syn::Fields::Named(named) => {
+            let names = named.named.iter().map(|f| f.ident.as_ref().unwrap());
+            quote!({ #(#names: #bindings),* })
         }
-    
-        _ => unimplemented!("for {:?}", f)
+        syn::Fields::Unnamed(_) => quote!(( #(#bindings),* )),
+        syn::Fields::Unit => quote!()
+    };
+
+    (pattern, bindings)
+}
+
+fn fields_constructor(fields:&syn::Fields, values:&[TokenStream2]) -> TokenStream2 {
+    match fields {
+        syn::Fields::Named(named) => {
+            let names = named.named.iter().map(|f| f.ident.as_ref().unwrap());
+            quote!({ #(#names: #values),* })
+        }
+        syn::Fields::Unnamed(_) => quote!(( #(#values),* )),
+        syn::Fields::Unit => quote!()
     }
 }
 
-fn impl_proto_writer(ast: &syn::DeriveInput) -> TokenStream {
+// the #[proto(other)] variant absorbs unknown tags: a unit variant drops them, `(tag)` keeps the tag
+// and `(tag, Vec<u8>)` also keeps the raw payload of a #[proto(length_prefixed)] enum
+fn other_variant<'a>(container:&attrs::ContainerAttrs, variants:&'a Punctuated<syn::Variant, syn::token::Comma>) -> syn::Result<Option<&'a syn::Variant>> {
+    let mut other = None;
+
+    for v in variants.iter() {
+        if !attrs::VariantAttrs::parse(v)?.other {
+            continue;
+        }
+
+        if other.is_some() {
+            return Err(syn::Error::new_spanned(v, "only one variant can be #[proto(other)]"));
+        }
+
+        match (&v.fields, v.fields.len()) {
+            (syn::Fields::Unit, _) | (syn::Fields::Unnamed(_), 1) => {}
+            (syn::Fields::Unnamed(_), 2) if container.length_prefixed => {}
+            (syn::Fields::Unnamed(_), 2) => {
+                return Err(syn::Error::new_spanned(v, "keeping the raw payload needs #[proto(length_prefixed)] on the enum"));
+            }
+            _ => return Err(syn::Error::new_spanned(v, "#[proto(other)] variant must be a unit, `(tag)` or `(tag, Vec<u8>)` variant"))
+        }
+
+        other = Some(v);
+    }
+
+    Ok(other)
+}
+
+// unit variants and variants with only skipped fields never touch the buffer
+fn fields_use_buf(fields:&syn::Fields) -> syn::Result<bool> {
+    for f in fields.iter() {
+        if !attrs::FieldAttrs::parse(&f.attrs)?.skip {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn version_writer(container:&attrs::ContainerAttrs) -> TokenStream2 {
+    match container.version {
+        Some(version) => quote!(<u32 as proto_buffer::ProtoWriter>::try_proto_write(&#version, buf)?;),
+        None => quote!()
+    }
+}
+
+// older versions are read as the type the first migration function takes and upgraded step by step,
+// e.g. version 1 of a version 3 type becomes `migrate_v2_to_v3(migrate_v1_to_v2(...))`
+fn version_reader(name:&syn::Ident, container:&attrs::ContainerAttrs, mode:ReadMode) -> TokenStream2 {
+    let version = match container.version {
+        Some(version) => version,
+        None => return quote!()
+    };
+
+    let min_version = container.min_version.map(|(min, _)| min).unwrap_or(1);
+    let reader_trait = mode.reader_trait();
+    let read_fn = mode.read_fn();
+    let mut arms = quote!();
+
+    for old in min_version..version {
+        let mut migrated = quote!(#reader_trait::#read_fn(buf)?);
+
+        for from in old..version {
+            let migrate = format_ident!("migrate_v{}_to_v{}", from, from + 1);
+
+            migrated = match &container.migrate {
+                Some(module) => quote!(#module::#migrate(#migrated)),
+                None => quote!(#migrate(#migrated))
+            };
+        }
+
+        arms.extend(quote!(
+            #old => return Ok(#migrated),
+        ));
+    }
+
+    let name_str = name.to_string();
+
+    quote! {
+        let offset = proto_buffer::ProtoSource::pos(buf);
+
+        match <u32 as #reader_trait>::#read_fn(buf)? {
+            #version => {}
+            #arms
+            version => return Err(proto_buffer::DecodeError::UnknownVersion { offset, name: #name_str, version })
+        }
+    }
+}
+
+fn impl_proto_writer(ast: &syn::DeriveInput) -> syn::Result<TokenStream2> {
     let name = &ast.ident;
+    let container = attrs::ContainerAttrs::parse(ast)?;
+    let generics = bounded_generics(&ast.generics, quote!(proto_buffer::ProtoWriter), &container.write_bound);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
     let mut writers = quote!();
     
     let gen = 
         match &ast.data {
             syn::Data::Struct(s) => {   
-                if s.fields.is_empty() {
-                    unimplemented!("for empty structs")
+                let numbered = protobuf::numbered_fields(&s.fields)?;
+                attrs::check_fields(&s.fields, fields_kind(&container, &numbered))?;
+
+                if container.evolvable {
+                    writers = evolve::evolvable_writer(&evolve::evolvable_fields(&s.fields, numbered.as_deref())?)?;
+                } else {
+                    for (pos, f) in s.fields.iter().enumerate() {
+                        let member = struct_field_member(pos, f);
+                        let writer = writer_by_field_ty(f, quote!(&self.#member))?;
+                        writers.extend(quote!(#writer));
+                    }
                 }
 
-                for f in s.fields.iter() {
-                    let writer = writer_by_field_ty(f, false);
-                    writers.extend(quote!(#writer));
-                }                    
+                let version = version_writer(&container);
+                writers = quote!(#version #writers);
+
+                if let Some(fields) = numbered {
+                    return impl_message_writer(name, &generics, &fields, writers);
+                }
 
                 quote! {
-                    impl proto_buffer::ProtoWriter for #name {
-                        fn proto_write(&self, buf:&mut proto_buffer::Buffer) {
+                    impl #impl_generics proto_buffer::ProtoWriter for #name #ty_generics #where_clause {
+                        fn try_proto_write<__S: proto_buffer::ProtoSink>(&self, buf:&mut __S) -> Result<(), proto_buffer::EncodeError> {
                             #writers
+
+                            Ok(())
                         }
                     }
                 }
             }
 
-            syn::Data::Enum(syn::DataEnum {variants, ..}) => { 
-                if variants.is_empty() {
-                    unimplemented!("for empty enums")
+            syn::Data::Enum(syn::DataEnum {variants, ..}) if variants.is_empty() => {
+                quote! {
+                    impl #impl_generics proto_buffer::ProtoWriter for #name #ty_generics #where_clause {
+                        fn try_proto_write<__S: proto_buffer::ProtoSink>(&self, _buf:&mut __S) -> Result<(), proto_buffer::EncodeError> {
+                            match *self {}
+                        }
+                    }
                 }
+            }
 
-                let eliter_ty = enum_ident_by_variants_len(&variants.len());
+            syn::Data::Enum(syn::DataEnum {variants, ..}) => { 
+                let (eliter_ty, eliters) = enum_tags(&container, variants)?;
+                let other = other_variant(&container, variants)?;
 
-                for (pos, v) in variants.iter().enumerate() {                    
+                for (v, eliter) in variants.iter().zip(eliters.iter()) {
                     let enum_name = &v.ident;
+                    attrs::check_fields(&v.fields, attrs::FieldsKind::Variant)?;
+
+                    let (pattern, bindings) = variant_pattern(&v.fields);
+                    let mut field_writers = quote!();
+
+                    if other == Some(v) && !v.fields.is_empty() {
+                        let payload = match (container.length_prefixed, bindings.get(1)) {
+                            (true, Some(raw)) => quote!(buf.try_write_len_prefixed_bytes(#raw)?;),
+                            (true, None) => quote!(buf.try_write_len(0)?;),
+                            (false, _) => quote!()
+                        };
+
+                        writers.extend(quote!(
+                            #name::#enum_name #pattern => {
+                                <#eliter_ty as proto_buffer::ProtoWriter>::try_proto_write(v0, buf)?;
+                                #payload
+                            }
+                        ));
+                        continue;
+                    }
 
-                    let eliter = enum_liter_by_pos(&pos, &variants.len());
+                    for (f, binding) in v.fields.iter().zip(bindings.iter()) {
+                        field_writers.extend(writer_by_field_ty(f, quote!(#binding))?);
+                    }
 
-                    match v.fields.len() {
-                        0 => {
-                            writers.extend(quote!(
-                                #name::#enum_name => {
-                                    #eliter_ty::proto_write(&#eliter, buf) 
-                                }
-                            ));
-                        }
-                        1 => {
-                            let f = &v.fields.iter().next().unwrap();
-                            let writer = writer_by_field_ty(f, true);
-
-                            writers.extend(quote!(
-                                #name::#enum_name(v) => {
-                                    #eliter_ty::proto_write(&#eliter, buf);
-                                    #writer 
-                                }
-                            ));
-                        }
-                        n => {
-                            unimplemented!("for {} fields in enum", n)
-                        }
+                    if container.length_prefixed {
+                        let param = if fields_use_buf(&v.fields)? { quote!(buf) } else { quote!(_) };
+                        field_writers = quote!(buf.try_write_len_prefixed(|#param| { #field_writers Ok(()) })?;);
                     }
+
+                    writers.extend(quote!(
+                        #name::#enum_name #pattern => {
+                            <#eliter_ty as proto_buffer::ProtoWriter>::try_proto_write(&#eliter, buf)?;
+                            #field_writers
+                        }
+                    ));
                 }
 
+                let version = version_writer(&container);
+
                 quote! {
-                    impl proto_buffer::ProtoWriter for #name {
-                        fn proto_write(&self, buf:&mut proto_buffer::Buffer) {
+                    impl #impl_generics proto_buffer::ProtoWriter for #name #ty_generics #where_clause {
+                        fn try_proto_write<__S: proto_buffer::ProtoSink>(&self, buf:&mut __S) -> Result<(), proto_buffer::EncodeError> {
+                            #version
+
                             match self {
                                 #writers
                             }
+
+                            Ok(())
                         }
                     }
                 }
             }
 
-            syn::Data::Union(_) => { unimplemented!("for Union") }
+            syn::Data::Union(_) => return Err(syn::Error::new_spanned(ast, "unions are not supported"))
         };
 
     //println!("{}", gen);
-    gen.into()
+    Ok(gen)
 }
 
-fn reader_by_field_ty(f:&syn::Field, is_enum: bool) -> TokenStream2 {
-    match &f.ty {
-        syn::Type::Path(tp) if f.ident.is_some() && !is_enum  => {
-            if tp.path.segments.len() == 1 {
-                match &tp.path.segments.first().unwrap().ident.to_string()[..] {
-                    "String" | "u8" | "i8" | "u16" | "i16" | "u32" | "i32" | "u64" | "i64" | "usize" |
-                    "f64" | "f32" | "bool" | "()" => {
-                        let field_name = f.ident.as_ref().unwrap();
-                        let ty = &tp.path.segments.first().unwrap().ident;
-                        return quote!(
-                            #field_name: #ty::proto_read(buf),
-                        );
-                    }
-                    v => {
-                        unimplemented!("for {} {}", v, f.ident.as_ref().unwrap().to_string())
-                    }
-                }
-            } else { unimplemented!("for none ident") }
-        }
+fn reader_by_field_ty(f:&syn::Field, mode:ReadMode) -> syn::Result<TokenStream2> {
+    let ty = &f.ty;
+    let field_attrs = attrs::FieldAttrs::parse(&f.attrs)?;
 
-        syn::Type::Path(tp) if f.ident.is_none() && is_enum  => {
-            if tp.path.segments.len() == 1 {
-                match &tp.path.segments.first().unwrap().ident.to_string()[..] {
-                    "String" | "u8" | "i8" | "u16" | "i16" | "u32" | "i32" | "u64" | "i64" | "usize" |
-                    "f64" | "f32" | "bool" | "()" => {
-                        let ty = &tp.path.segments.first().unwrap().ident;
-                        return quote!(
-                            #ty::proto_read(buf)
-                        );
-                    }
-                    v => {
-                        unimplemented!("for {} {}", v, f.ident.as_ref().unwrap().to_string())
-                    }
-                }
-            } else { unimplemented!("for none ident") }
-        }
-    
-        _ => unimplemented!("for {:?}", f)
-    }    
+    if field_attrs.skip {
+        return Ok(field_attrs.default_value(ty));
+    }
+
+    if let Some(with) = &field_attrs.with {
+        let with_fn = mode.with_fn();
+
+        return Ok(quote_spanned!(with.span()=>
+            #with::#with_fn(buf)?
+        ));
+    }
+
+    let reader_trait = mode.reader_trait();
+    let read_fn = mode.read_fn();
+
+    Ok(quote_spanned!(ty.span()=>
+        <#ty as #reader_trait>::#read_fn(buf)?
+    ))
 }
 
-fn impl_proto_reader(ast: &syn::DeriveInput) -> TokenStream {
+fn impl_proto_reader(ast: &syn::DeriveInput, mode:ReadMode) -> syn::Result<TokenStream2> {
     let name = &ast.ident;
+    let container = attrs::ContainerAttrs::parse(ast)?;
+    let reader_trait = mode.reader_trait();
+    let read_fn = mode.read_fn();
+    let read_params = mode.read_params();
+    let generics = bounded_generics(&ast.generics, reader_trait.clone(), &container.read_bound);
+
+    // the borrowed reader gets its own 'de lifetime that outlives every lifetime of the type
+    let mut impl_generics = generics.clone();
+
+    if mode == ReadMode::Borrowed {
+        let mut de = syn::LifetimeDef::new(syn::Lifetime::new("'de", proc_macro2::Span::call_site()));
+        de.bounds.extend(generics.lifetimes().map(|l| l.lifetime.clone()));
+        impl_generics.params.insert(0, syn::GenericParam::Lifetime(de));
+    }
+
+    let (impl_generics, _, where_clause) = impl_generics.split_for_impl();
+    let (_, ty_generics, _) = generics.split_for_impl();
 
     let mut readers = quote!();
     
     let gen = 
         match &ast.data {
             syn::Data::Struct(s) => {   
-                for f in s.fields.iter() {
-                    let reader = reader_by_field_ty(&f, false);
-                    readers.extend(quote!(#reader));
-                }                    
+                let numbered = protobuf::numbered_fields(&s.fields)?;
+                attrs::check_fields(&s.fields, fields_kind(&container, &numbered))?;
+
+                if mode == ReadMode::Borrowed && numbered.is_some() && !container.evolvable {
+                    return Err(syn::Error::new_spanned(&ast.ident, "borrowed reading of protobuf messages is not supported"));
+                }
+
+                if container.evolvable {
+                    let fields = evolve::evolvable_fields(&s.fields, numbered.as_deref())?;
+                    readers = evolve::evolvable_reader(name, &s.fields, &fields, mode)?;
+                } else {
+                    let field_readers = s.fields.iter().map(|f| reader_by_field_ty(f, mode)).collect::<syn::Result<Vec<_>>>()?;
+                    let constructor = fields_constructor(&s.fields, &field_readers);
+                    readers = quote!(Ok(#name #constructor));
+                }
+
+                let version = version_reader(name, &container, mode);
+                readers = quote!(#version #readers);
+
+                if let (ReadMode::Owned, Some(fields)) = (mode, numbered) {
+                    return impl_message_reader(name, &generics, &fields, readers);
+                }
 
                 quote! {
-                    impl proto_buffer::ProtoReader for #name {
-                        fn proto_read(buf:&mut proto_buffer::Buffer) -> Self {
-                           return #name {
-                                #readers
-                           }
+                    impl #impl_generics #reader_trait for #name #ty_generics #where_clause {
+                        fn #read_fn #read_params -> Result<Self, proto_buffer::DecodeError> {
+                           #readers
                         }
                     }
                 }
             }
-            syn::Data::Enum(syn::DataEnum {variants, ..}) => { 
-                if variants.is_empty() {
-                    unimplemented!("for empty enums")
-                }
 
-                let eliter_ty = enum_ident_by_variants_len(&variants.len());
+            // nothing was written for a value that can not exist, so nothing is read either
+            syn::Data::Enum(syn::DataEnum {variants, ..}) if variants.is_empty() => {
+                let name_str = name.to_string();
 
-                for (pos, v) in variants.iter().enumerate() {                    
-                    let eliter = enum_liter_by_pos(&pos, &variants.len());
+                quote! {
+                    impl #impl_generics #reader_trait for #name #ty_generics #where_clause {
+                        fn #read_fn #read_params -> Result<Self, proto_buffer::DecodeError> {
+                            Err(proto_buffer::DecodeError::Uninhabited { offset: proto_buffer::ProtoSource::pos(buf), name: #name_str })
+                        }
+                    }
+                }
+            }
+            syn::Data::Enum(syn::DataEnum {variants, ..}) => { 
+                let (eliter_ty, eliters) = enum_tags(&container, variants)?;
+                let other = other_variant(&container, variants)?;
 
+                for (v, eliter) in variants.iter().zip(eliters.iter()) {
                     let enum_name = &v.ident;
+                    attrs::check_fields(&v.fields, attrs::FieldsKind::Variant)?;
 
-                    match v.fields.len() {
-                        0 => {
-                            readers.extend(quote!(
-                                #eliter => { #name::#enum_name }
-                            ));
-                        }
-                        1 => {
-                            let f = &v.fields.iter().next().unwrap();
-                            let reader = reader_by_field_ty(f, true);
-
-                            readers.extend(quote!(
-                                #eliter => {
-                                    #name::#enum_name(#reader)
-                                }
-                            ));
-                        }
-                        n => {
-                            unimplemented!("for {} fields in enum", n)
-                        }
+                    // the other variant is only reached through the fallback arm
+                    if other == Some(v) {
+                        continue;
+                    }
+
+                    let field_readers = v.fields.iter().map(|f| reader_by_field_ty(f, mode)).collect::<syn::Result<Vec<_>>>()?;
+                    let constructor = fields_constructor(&v.fields, &field_readers);
+
+                    if container.length_prefixed {
+                        let param = if fields_use_buf(&v.fields)? { quote!(buf) } else { quote!(_) };
+
+                        readers.extend(quote!(
+                            #eliter => {
+                                proto_buffer::ProtoSource::try_read_len_prefixed(buf, |#param| Ok(#name::#enum_name #constructor))
+                            }
+                        ));
+                    } else {
+                        readers.extend(quote!(
+                            #eliter => {
+                                Ok(#name::#enum_name #constructor)
+                            }
+                        ));
                     }
                 }
 
-                let name_str = name.to_string();
+                let (offset, fallback) = match other {
+                    Some(v) => {
+                        let enum_name = &v.ident;
+
+                        let fallback = match (container.length_prefixed, v.fields.len()) {
+                            (false, 0) => quote!(Ok(#name::#enum_name)),
+                            (false, _) => quote!(Ok(#name::#enum_name(n))),
+                            (true, 0) => quote!({ proto_buffer::ProtoSource::try_skip_len_prefixed(buf)?; Ok(#name::#enum_name) }),
+                            (true, 1) => quote!({ proto_buffer::ProtoSource::try_skip_len_prefixed(buf)?; Ok(#name::#enum_name(n)) }),
+                            (true, _) => quote!(Ok(#name::#enum_name(n, proto_buffer::ProtoSource::try_read_len_prefixed_bytes(buf)?)))
+                        };
+
+                        let tag = if v.fields.is_empty() { quote!(_) } else { quote!(n) };
+
+                        (quote!(), quote!(#tag => #fallback))
+                    }
+                    None => {
+                        let name_str = name.to_string();
+
+                        (
+                            quote!(let offset = proto_buffer::ProtoSource::pos(buf);),
+                            quote!(n => Err(proto_buffer::DecodeError::UnknownVariant { offset, name: #name_str, variant: n as u64 }))
+                        )
+                    }
+                };
+
+                let version = version_reader(name, &container, mode);
 
                 quote! {
-                    impl proto_buffer::ProtoReader for #name {
-                        fn proto_read(buf:&mut proto_buffer::Buffer) -> Self {
-                           return match #eliter_ty::proto_read(buf) {
+                    impl #impl_generics #reader_trait for #name #ty_generics #where_clause {
+                        fn #read_fn #read_params -> Result<Self, proto_buffer::DecodeError> {
+                           #version
+                           #offset
+
+                           match <#eliter_ty as #reader_trait>::#read_fn(buf)? {
                                 #readers
-                                n => panic!("wrong read {} from {}", #name_str, n)
+                                #fallback
                            }
                         }
                     }
                 }
             }
-            syn::Data::Union(_) => { unimplemented!("for Union") }
+            syn::Data::Union(_) => return Err(syn::Error::new_spanned(ast, "unions are not supported"))
         };
 
     //println!("{}", gen);
-    gen.into()
+    Ok(gen)
 }
 
-#[proc_macro_derive(ProtoBufferReader)]
+// structs with field numbers also read and write themselves as length-delimited protobuf messages in
+// Encoding::Protobuf, `writers` is the body used for the other encodings
+fn impl_message_writer(name: &syn::Ident, generics: &syn::Generics, fields: &[protobuf::NumberedField], writers: TokenStream2) -> syn::Result<TokenStream2> {
+    let generics = protobuf::field_bounded_generics(generics, quote!(proto_buffer::protobuf::FieldWriter), fields);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let message_writer = protobuf::message_writer(name, &generics, fields);
+
+    Ok(quote! {
+        #message_writer
+
+        impl #impl_generics proto_buffer::ProtoWriter for #name #ty_generics #where_clause {
+            fn try_proto_write<__S: proto_buffer::ProtoSink>(&self, buf:&mut __S) -> Result<(), proto_buffer::EncodeError> {
+                if buf.encoding() == proto_buffer::Encoding::Protobuf {
+                    return proto_buffer::protobuf::ValueWriter::try_write_value(self, buf);
+                }
+
+                #writers
+
+                Ok(())
+            }
+        }
+    })
+}
+
+fn impl_message_reader(name: &syn::Ident, generics: &syn::Generics, fields: &[protobuf::NumberedField], readers: TokenStream2) -> syn::Result<TokenStream2> {
+    let generics = protobuf::field_bounded_generics(generics, quote!(proto_buffer::protobuf::FieldReader + Default), fields);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let message_reader = protobuf::message_reader(name, &generics, fields);
+
+    Ok(quote! {
+        #message_reader
+
+        impl #impl_generics proto_buffer::ProtoReader for #name #ty_generics #where_clause {
+            fn try_proto_read<__S: proto_buffer::ProtoSource>(buf:&mut __S) -> Result<Self, proto_buffer::DecodeError> {
+                if buf.encoding() == proto_buffer::Encoding::Protobuf {
+                    proto_buffer::protobuf::ValueReader::try_read_value(buf)
+                } else {
+                    #readers
+                }
+            }
+        }
+    })
+}
+
+#[proc_macro_derive(ProtoBufferReader, attributes(proto))]
 pub fn proto_buffer_reader_derive(input: TokenStream) -> TokenStream {
-    let ast = syn::parse(input).unwrap();
-    impl_proto_reader(&ast)
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    match impl_proto_reader(&ast, ReadMode::Owned) {
+        Ok(gen) => gen.into(),
+        Err(e) => e.to_compile_error().into()
+    }
 }
 
-#[proc_macro_derive(ProtoBufferWriter)]
+#[proc_macro_derive(ProtoBufferBorrowReader, attributes(proto))]
+pub fn proto_buffer_borrow_reader_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    match impl_proto_reader(&ast, ReadMode::Borrowed) {
+        Ok(gen) => gen.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
+
+#[proc_macro_derive(ProtoBufferWriter, attributes(proto))]
 pub fn proto_buffer_writer_derive(input: TokenStream) -> TokenStream {
-    let ast = syn::parse(input).unwrap();
-    impl_proto_writer(&ast)
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    match impl_proto_writer(&ast) {
+        Ok(gen) => gen.into(),
+        Err(e) => e.to_compile_error().into()
+    }
 }
 
 
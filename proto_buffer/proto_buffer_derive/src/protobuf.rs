@@ -0,0 +1,145 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, format_ident};
+
+use crate::attrs::FieldAttrs;
+
+const MAX_FIELD_NUMBER: u32 = (1 << 29) - 1;
+
+// `number` is None for skipped fields
+pub struct NumberedField<'a> {
+    pub field: &'a syn::Field,
+    pub number: Option<u32>,
+    pub attrs: FieldAttrs,
+}
+
+// returns None for structs without any #[proto(field = N)], they keep the positional layout only
+pub fn numbered_fields(fields: &syn::Fields) -> syn::Result<Option<Vec<NumberedField<'_>>>> {
+    let mut numbered: Vec<NumberedField> = Vec::new();
+    let mut unnumbered = Vec::new();
+
+    for f in fields.iter() {
+        let attrs = FieldAttrs::parse(&f.attrs)?;
+
+        match attrs.field {
+            Some((number, span)) => {
+                if number == 0 || number > MAX_FIELD_NUMBER || (19000..=19999).contains(&number) {
+                    return Err(syn::Error::new(span, "field number must be in 1..=536870911 and outside 19000..=19999"));
+                }
+
+                if numbered.iter().any(|n| n.number == Some(number)) {
+                    return Err(syn::Error::new(span, format!("duplicate field number {}", number)));
+                }
+
+                numbered.push(NumberedField { field: f, number: Some(number), attrs });
+            }
+            None if attrs.skip => numbered.push(NumberedField { field: f, number: None, attrs }),
+            None => unnumbered.push(f)
+        }
+    }
+
+    if numbered.iter().all(|n| n.number.is_none()) {
+        return Ok(None);
+    }
+
+    if let Some(f) = unnumbered.first() {
+        return Err(syn::Error::new_spanned(f, "every field needs #[proto(field = N)] once one field has it"));
+    }
+
+    if let syn::Fields::Unnamed(_) = fields {
+        return Err(syn::Error::new_spanned(fields, "field numbers are supported on structs with named fields only"));
+    }
+
+    Ok(Some(numbered))
+}
+
+// generic messages need every field type to be encodable, not only the type parameters
+pub fn field_bounded_generics(generics: &syn::Generics, bound: TokenStream2, fields: &[NumberedField]) -> syn::Generics {
+    let mut generics = generics.clone();
+
+    if generics.type_params().next().is_some() {
+        let predicates = fields.iter().filter(|f| f.number.is_some()).map(|f| -> syn::WherePredicate {
+            let ty = &f.field.ty;
+            syn::parse_quote!(#ty: #bound)
+        });
+
+        generics.make_where_clause().predicates.extend(predicates);
+    }
+
+    generics
+}
+
+pub fn message_writer(name: &syn::Ident, generics: &syn::Generics, fields: &[NumberedField]) -> TokenStream2 {
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let mut writers = quote!();
+
+    for NumberedField { field, number, .. } in fields {
+        let field_name = field.ident.as_ref().unwrap();
+        let number = match number {
+            Some(number) => number,
+            None => continue
+        };
+
+        writers.extend(quote!(
+            proto_buffer::protobuf::FieldWriter::try_write_field(&self.#field_name, #number, buf)?;
+        ));
+    }
+
+    quote! {
+        impl #impl_generics proto_buffer::protobuf::MessageWriter for #name #ty_generics #where_clause {
+            fn try_write_fields<__S: proto_buffer::ProtoSink>(&self, buf:&mut __S) -> Result<(), proto_buffer::EncodeError> {
+                #writers
+
+                Ok(())
+            }
+        }
+    }
+}
+
+pub fn message_reader(name: &syn::Ident, generics: &syn::Generics, fields: &[NumberedField]) -> TokenStream2 {
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let mut vars = quote!();
+    let mut arms = quote!();
+    let mut inits = quote!();
+
+    for NumberedField { field, number, attrs } in fields {
+        let field_name = field.ident.as_ref().unwrap();
+        let var = format_ident!("field_{}", field_name);
+        let ty = &field.ty;
+        let default = attrs.default_value(ty);
+
+        vars.extend(quote!(
+            let mut #var: #ty = #default;
+        ));
+
+        if let Some(number) = number {
+            arms.extend(quote!(
+                #number => proto_buffer::protobuf::FieldReader::merge_field(&mut #var, wire_type, buf)?,
+            ));
+        }
+
+        inits.extend(quote!(
+            #field_name: #var,
+        ));
+    }
+
+    quote! {
+        impl #impl_generics proto_buffer::protobuf::MessageReader for #name #ty_generics #where_clause {
+            fn try_read_fields<__S: proto_buffer::ProtoSource>(buf:&mut __S, end: usize) -> Result<Self, proto_buffer::DecodeError> {
+                #vars
+
+                while buf.pos() < end {
+                    let (number, wire_type) = proto_buffer::protobuf::try_read_key(buf)?;
+
+                    match number {
+                        #arms
+                        _ => proto_buffer::protobuf::skip_field(wire_type, buf)?
+                    }
+                }
+
+                Ok(#name {
+                    #inits
+                })
+            }
+        }
+    }
+}
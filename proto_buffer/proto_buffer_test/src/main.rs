@@ -14,13 +14,381 @@ mod tests {
         Nothing
     }
 
-    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader, ProtoBufferBorrowReader)]
     struct User {
         name: String,
         email: String,
         age: u8
     }
 
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct Profile {
+        user: User,
+        status: Box<UserStatus>,
+        nick: Option<String>,
+        visits: Vec<u32>,
+        about: std::string::String,
+        friends: Vec<User>
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    enum Event {
+        Login (User),
+        Tags (Vec<String>),
+        Visit (Option<u64>),
+        Logout
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    enum Command {
+        Move { x: i32, y: i32 },
+        Say (String, u8, bool),
+        Rename { buf: String },
+        Stop
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(tag_type = "u16")]
+    enum Shape {
+        #[proto(tag = 10)]
+        Square (u8),
+        Circle { radius: u8 },
+        #[proto(tag = 2)]
+        Dot
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(tag_type = "u16")]
+    enum Level {
+        Low = 1,
+        High = 300,
+        Max
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(length_prefixed)]
+    enum MessageV2 {
+        Ping,
+        Text (String),
+        Image { width: u16, data: Vec<u8> }
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(length_prefixed)]
+    enum MessageV1 {
+        Ping,
+        Text (String),
+        #[proto(other)]
+        Unknown (u8, Vec<u8>)
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(length_prefixed)]
+    enum MessageV0 {
+        Ping,
+        #[proto(other)]
+        Unknown
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    enum Color {
+        Red,
+        Green,
+        #[proto(other)]
+        Unknown (u8)
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct UserId(u64);
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct Point(i32, i32, String);
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct Marker;
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    enum Never {}
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct Page<T> {
+        items: Vec<T>,
+        total: u32
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    enum Either<L, R> where L: Clone {
+        Left (L),
+        Right { value: R }
+    }
+
+    #[derive(ProtoBufferWriter)]
+    struct Named<'a, T> {
+        name: &'a str,
+        value: T
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(bound(write = "T: ProtoWriter + Clone", read = "T: ProtoReader + Clone"))]
+    struct Pair<T> {
+        first: T,
+        second: T
+    }
+
+    #[derive(Debug, PartialEq, Default, ProtoBufferWriter, ProtoBufferReader)]
+    struct Test1 {
+        #[proto(field = 1)]
+        a: i32
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct Test3 {
+        #[proto(field = 3)]
+        c: Test1
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct Test4 {
+        #[proto(field = 3)]
+        c: Test1,
+        #[proto(field = 4)]
+        d: Vec<i32>
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct Blob {
+        #[proto(field = 1)]
+        data: protobuf::Bytes,
+        #[proto(field = 2)]
+        codes: Vec<u8>
+    }
+
+    // a message between positional fields
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct Outer {
+        x: u8,
+        t: Test1,
+        y: u8
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct Account {
+        #[proto(field = 1)]
+        id: u64,
+        #[proto(field = 2)]
+        name: String,
+        #[proto(field = 3)]
+        balance: f64,
+        #[proto(field = 4)]
+        ratio: f32,
+        #[proto(field = 5)]
+        tags: Vec<String>,
+        #[proto(field = 6)]
+        scores: Vec<i32>,
+        #[proto(field = 7)]
+        owner: Option<Test1>,
+        #[proto(field = 8)]
+        history: Vec<Test1>
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct Batch<T> {
+        #[proto(field = 1)]
+        items: Vec<T>,
+        #[proto(field = 2)]
+        total: u32
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct AccountName {
+        #[proto(field = 2)]
+        name: String
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct Session {
+        user: User,
+        #[proto(skip)]
+        cache: Vec<u8>,
+        #[proto(skip, default = "default_retries")]
+        retries: u8,
+        #[proto(with = "duration_ms")]
+        timeout: std::time::Duration
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    enum Job {
+        Run { name: String, #[proto(skip)] started: bool },
+        Wait (#[proto(with = "duration_ms")] std::time::Duration)
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct CachedAccount {
+        #[proto(field = 1)]
+        id: u64,
+        #[proto(skip, default = "default_retries")]
+        retries: u8,
+        #[proto(field = 2, default = "default_name")]
+        name: String
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(evolvable)]
+    struct ContactV1 {
+        name: String,
+        age: u8
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(evolvable)]
+    struct ContactV2 {
+        name: String,
+        age: u8,
+        #[proto(default = "default_name")]
+        email: String,
+        tags: Vec<String>
+    }
+
+    // age and email were removed, the remaining ids stay the same
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(evolvable)]
+    struct ContactV3 {
+        #[proto(id = 1)]
+        name: String,
+        #[proto(id = 4)]
+        tags: Vec<String>
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(evolvable)]
+    struct ContactV4 {
+        #[proto(id = 1)]
+        name: String,
+        #[proto(id = 5, default = "default_status")]
+        status: UserStatus,
+        #[proto(id = 4)]
+        tags: Vec<String>
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(evolvable)]
+    struct Rgb(u8, u8, #[proto(skip)] u8);
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct SettingsV1 {
+        volume: u8
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    struct SettingsV2 {
+        volume: u8,
+        theme: String
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(version = 3, migrate = "settings_migrations")]
+    struct Settings {
+        volume: u16,
+        theme: String,
+        dark: bool
+    }
+
+    mod settings_migrations {
+        use super::*;
+
+        pub fn migrate_v1_to_v2(v: SettingsV1) -> SettingsV2 {
+            SettingsV2 { volume: v.volume, theme: String::from("light") }
+        }
+
+        pub fn migrate_v2_to_v3(v: SettingsV2) -> Settings {
+            Settings { volume: v.volume as u16 * 10, dark: v.theme == "dark", theme: v.theme }
+        }
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    enum ModeV1 {
+        Fast,
+        Slow
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(version = 2)]
+    enum Mode {
+        Off,
+        Fast,
+        Slow
+    }
+
+    fn migrate_v1_to_v2(v: ModeV1) -> Mode {
+        match v {
+            ModeV1::Fast => Mode::Fast,
+            ModeV1::Slow => Mode::Slow
+        }
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferReader)]
+    #[proto(version = 5, min_version = 5)]
+    struct Limits {
+        max: u32
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferBorrowReader)]
+    struct LogLine<'a> {
+        level: u8,
+        message: &'a str,
+        payload: &'a [u8],
+        tags: Vec<&'a str>,
+        source: Option<std::borrow::Cow<'a, str>>,
+        user: User
+    }
+
+    #[derive(Debug, PartialEq, ProtoBufferWriter, ProtoBufferBorrowReader)]
+    #[proto(length_prefixed)]
+    enum Value<'a> {
+        Text (&'a str),
+        Number (u64),
+        #[proto(other)]
+        Unknown
+    }
+
+    fn default_retries() -> u8 {
+        3
+    }
+
+    fn default_name() -> String {
+        String::from("unknown")
+    }
+
+    fn default_status() -> UserStatus {
+        UserStatus::Nothing
+    }
+
+    mod duration_ms {
+        use proto_buffer::*;
+        use std::time::Duration;
+
+        pub fn write<S: ProtoSink>(v: &Duration, buf: &mut S) -> Result<(), EncodeError> {
+            (v.as_millis() as u64).try_proto_write(buf)
+        }
+
+        pub fn read<S: ProtoSource>(buf: &mut S) -> Result<Duration, DecodeError> {
+            Ok(Duration::from_millis(u64::try_proto_read(buf)?))
+        }
+    }
+
+    fn account() -> Account {
+        Account {
+            id: 300,
+            name: String::from("Den"),
+            balance: -2.5,
+            ratio: 0.25,
+            tags: vec![String::from("a"), String::from("bc")],
+            scores: vec![3, 270, -1],
+            owner: Some(Test1 { a: 150 }),
+            history: vec![Test1 { a: 1 }, Test1 { a: 2 }]
+        }
+    }
+
     //cargo test -- --nocapture
     
     #[test]
@@ -37,8 +405,6 @@ mod tests {
 
         //println!("{:?}", b);
 
-        b.pos = 0;
-
         assert_eq!(us_student, UserStatus::proto_read(&mut b));
         assert_eq!(us_worker, UserStatus::proto_read(&mut b));
         assert_eq!(us_nothing, UserStatus::proto_read(&mut b));
@@ -58,11 +424,617 @@ mod tests {
 
         //println!("{:?}", b);
 
-        b.pos = 0;
-
         let readed_user = User::proto_read(&mut b);
 
         assert_eq!(user, readed_user);
     }
+
+    #[test]
+    fn profile() {
+        let mut b = Buffer::new();
+
+        let user = |name: &str| User {
+            name: String::from(name),
+            email: format!("{}@gmail.com", name),
+            age: 37
+        };
+
+        let profile = Profile {
+            user: user("Den"),
+            status: Box::new(UserStatus::Worker(String::from("Horns and hooves"))),
+            nick: Some(String::from("nastvood")),
+            visits: vec![1, 2, 3],
+            about: String::new(),
+            friends: vec![user("Ann"), user("Bob")]
+        };
+
+        profile.proto_write(&mut b);
+
+        assert_eq!(profile, Profile::proto_read(&mut b));
+    }
+
+    #[test]
+    fn event() {
+        let mut b = Buffer::new();
+
+        let events = vec![
+            Event::Login(User { name: String::from("Den"), email: String::from("nastvood@gmail.com"), age: 37 }),
+            Event::Tags(vec![String::from("rust"), String::from("proto")]),
+            Event::Visit(None),
+            Event::Visit(Some(42)),
+            Event::Logout
+        ];
+
+        events.proto_write(&mut b);
+
+        assert_eq!(events, Vec::<Event>::proto_read(&mut b));
+    }
+
+    #[test]
+    fn command() {
+        let mut b = Buffer::new();
+
+        Command::Move { x: -1, y: 2 }.proto_write(&mut b);
+
+        assert_eq!(&[0, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 2], b.as_slice());
+
+        let commands = vec![
+            Command::Move { x: 10, y: -20 },
+            Command::Say (String::from("Hello"), 3, true),
+            Command::Rename { buf: String::from("Den") },
+            Command::Stop
+        ];
+
+        b.clear();
+        commands.proto_write(&mut b);
+
+        assert_eq!(commands, Vec::<Command>::proto_read(&mut b));
+    }
+
+    #[test]
+    fn explicit_tags() {
+        let mut b = Buffer::new();
+
+        Shape::Square(7).proto_write(&mut b);
+        Shape::Circle { radius: 3 }.proto_write(&mut b);
+        Shape::Dot.proto_write(&mut b);
+
+        assert_eq!(&[0, 10, 7, 0, 11, 3, 0, 2], b.as_slice());
+
+        assert_eq!(Shape::Square(7), Shape::proto_read(&mut b));
+        assert_eq!(Shape::Circle { radius: 3 }, Shape::proto_read(&mut b));
+        assert_eq!(Shape::Dot, Shape::proto_read(&mut b));
+
+        let mut b = Buffer::new();
+
+        Level::Low.proto_write(&mut b);
+        Level::High.proto_write(&mut b);
+        Level::Max.proto_write(&mut b);
+
+        assert_eq!(&[0, 1, 1, 44, 1, 45], b.as_slice());
+
+        assert_eq!(Level::Low, Level::proto_read(&mut b));
+        assert_eq!(Level::High, Level::proto_read(&mut b));
+        assert_eq!(Level::Max, Level::proto_read(&mut b));
+
+        let mut b = Buffer::from_vec(vec![0, 0]);
+        assert_eq!(
+            Err(DecodeError::UnknownVariant { offset: 0, name: "Shape", variant: 0 }),
+            Shape::try_proto_read(&mut b)
+        );
+    }
+
+    #[test]
+    fn unknown_variants() {
+        let mut b = Buffer::new();
+        let image = MessageV2::Image { width: 2, data: vec![1, 2, 3] };
+
+        image.proto_write(&mut b);
+        MessageV2::Text(String::from("Den")).proto_write(&mut b);
+
+        let unknown = MessageV1::proto_read(&mut b);
+
+        assert!(matches!(&unknown, MessageV1::Unknown(2, raw) if raw.len() == 2 + 8 + 3));
+        assert_eq!(MessageV1::Text(String::from("Den")), MessageV1::proto_read(&mut b));
+
+        b.pos = 0;
+        assert_eq!(MessageV0::Unknown, MessageV0::proto_read(&mut b));
+        assert_eq!(MessageV0::Unknown, MessageV0::proto_read(&mut b));
+        assert_eq!(b.len(), b.pos);
+
+        // the raw payload is written back untouched
+        let mut b = Buffer::new();
+        unknown.proto_write(&mut b);
+
+        assert_eq!(image, MessageV2::proto_read(&mut b));
+
+        let mut b = Buffer::new();
+        MessageV2::Ping.proto_write(&mut b);
+        assert_eq!(&[0, 0, 0, 0, 0, 0, 0, 0, 0], b.as_slice());
+
+        let mut b = Buffer::from_vec(vec![1, 0, 0, 0, 0, 0, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            Err(DecodeError::InvalidLength { offset: 1, len: 9 }),
+            MessageV1::try_proto_read(&mut b)
+        );
+
+        let mut b = Buffer::from_vec(vec![1, 5]);
+        assert_eq!(Color::Green, Color::proto_read(&mut b));
+        assert_eq!(Color::Unknown(5), Color::proto_read(&mut b));
+
+        let mut b = Buffer::new();
+        Color::Unknown(7).proto_write(&mut b);
+        assert_eq!(&[7], b.as_slice());
+    }
+
+    #[test]
+    fn evolvable_structs() {
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Varint;
+
+        ContactV1 { name: String::from("a"), age: 2 }.proto_write(&mut b);
+        assert_eq!(&[7, 1, 2, 1, b'a', 2, 1, 2], b.as_slice());
+
+        let v2 = ContactV2 {
+            name: String::from("Den"),
+            age: 37,
+            email: String::from("den@mail.com"),
+            tags: vec![String::from("admin")]
+        };
+
+        let mut b = Buffer::new();
+        v2.proto_write(&mut b);
+        Rgb(1, 2, 3).proto_write(&mut b);
+
+        assert_eq!(ContactV1 { name: String::from("Den"), age: 37 }, ContactV1::proto_read(&mut b));
+        assert_eq!(Rgb(1, 2, 0), Rgb::proto_read(&mut b));
+
+        b.pos = 0;
+        assert_eq!(
+            ContactV3 { name: String::from("Den"), tags: vec![String::from("admin")] },
+            ContactV3::proto_read(&mut b)
+        );
+
+        let mut b = Buffer::new();
+        ContactV1 { name: String::from("Den"), age: 37 }.proto_write(&mut b);
+        ContactV3 { name: String::from("Den"), tags: vec![String::from("admin")] }.proto_write(&mut b);
+
+        assert_eq!(
+            ContactV2 { name: String::from("Den"), age: 37, email: default_name(), tags: Vec::new() },
+            ContactV2::proto_read(&mut b)
+        );
+        assert_eq!(
+            ContactV2 { name: String::from("Den"), age: 0, email: default_name(), tags: vec![String::from("admin")] },
+            ContactV2::proto_read(&mut b)
+        );
+        assert_eq!(b.len(), b.pos);
+
+        let mut b = Buffer::new();
+        ContactV3 { name: String::from("Den"), tags: vec![String::from("admin")] }.proto_write(&mut b);
+        ContactV4 { name: String::from("Den"), status: UserStatus::Student(3), tags: Vec::new() }.proto_write(&mut b);
+
+        assert_eq!(
+            ContactV4 { name: String::from("Den"), status: UserStatus::Nothing, tags: vec![String::from("admin")] },
+            ContactV4::proto_read(&mut b)
+        );
+        assert_eq!(
+            ContactV3 { name: String::from("Den"), tags: Vec::new() },
+            ContactV3::proto_read(&mut b)
+        );
+
+        let mut b = Buffer::from_vec(vec![0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 2, 0, 0]);
+        assert!(matches!(ContactV1::try_proto_read(&mut b), Err(DecodeError::UnexpectedEof { .. })));
+    }
+
+    #[test]
+    fn versions() {
+        let mut b = Buffer::new();
+        let settings = Settings { volume: 7, theme: String::from("dark"), dark: true };
+
+        settings.proto_write(&mut b);
+
+        let mut expected = Buffer::new();
+        3u32.proto_write(&mut expected);
+        7u16.proto_write(&mut expected);
+        "dark".proto_write(&mut expected);
+        true.proto_write(&mut expected);
+
+        assert_eq!(expected.as_slice(), b.as_slice());
+
+        1u32.proto_write(&mut b);
+        SettingsV1 { volume: 5 }.proto_write(&mut b);
+        2u32.proto_write(&mut b);
+        SettingsV2 { volume: 6, theme: String::from("dark") }.proto_write(&mut b);
+
+        assert_eq!(settings, Settings::proto_read(&mut b));
+        assert_eq!(Settings { volume: 50, theme: String::from("light"), dark: false }, Settings::proto_read(&mut b));
+        assert_eq!(Settings { volume: 60, theme: String::from("dark"), dark: true }, Settings::proto_read(&mut b));
+
+        let mut b = Buffer::new();
+        Mode::Off.proto_write(&mut b);
+        1u32.proto_write(&mut b);
+        ModeV1::Slow.proto_write(&mut b);
+
+        assert_eq!(Mode::Off, Mode::proto_read(&mut b));
+        assert_eq!(Mode::Slow, Mode::proto_read(&mut b));
+
+        let mut b = Buffer::new();
+        4u32.proto_write(&mut b);
+        1u32.proto_write(&mut b);
+
+        assert_eq!(
+            Err(DecodeError::UnknownVersion { offset: 0, name: "Limits", version: 4 }),
+            Limits::try_proto_read(&mut b)
+        );
+
+        b.pos = 0;
+        assert_eq!(
+            Err(DecodeError::UnknownVersion { offset: 0, name: "Settings", version: 4 }),
+            Settings::try_proto_read(&mut b)
+        );
+    }
+
+    #[test]
+    fn borrowed_writes() {
+        let points = vec![Point(1, 2, String::from("a")), Point(3, 4, String::from("b"))];
+
+        let mut b: Buffer = points.iter().collect();
+        points[1..].proto_write(&mut b);
+
+        assert_eq!(points, Vec::<Point>::proto_read(&mut b));
+        assert_eq!(&points[1..], &Vec::<Point>::proto_read(&mut b)[..]);
+    }
+
+    #[test]
+    fn borrowed_reads() {
+        let mut b = Buffer::new();
+
+        let line = LogLine {
+            level: 2,
+            message: "disk is full",
+            payload: &[1, 2, 3],
+            tags: vec!["disk", "alert"],
+            source: Some("node-1".into()),
+            user: User { name: String::from("Den"), email: String::from("den@mail.com"), age: 37 }
+        };
+
+        line.proto_write(&mut b);
+        Value::Text("abc").proto_write(&mut b);
+        Value::Number(7).proto_write(&mut b);
+
+        let mut view = b.view();
+
+        let read = LogLine::borrow_read(&mut view);
+        assert_eq!(line, read);
+
+        // strings point into the buffer instead of being copied
+        let data = b.as_slice().as_ptr_range();
+        assert!(data.contains(&read.message.as_ptr()));
+        assert!(data.contains(&read.payload.as_ptr()));
+        assert!(matches!(read.source, Some(std::borrow::Cow::Borrowed(_))));
+
+        assert_eq!(Value::Text("abc"), Value::borrow_read(&mut view));
+        assert_eq!(Value::Number(7), Value::borrow_read(&mut view));
+        assert_eq!(b.len(), view.pos);
+
+        // numeric vectors are read in bulk in either endian
+        for endian in [Endian::BigEndian, Endian::LittleEndian].iter() {
+            let mut b = Buffer::new();
+            b.endian = *endian;
+            vec![1.5f32, -2.0].proto_write(&mut b);
+            vec![1u32, 70000].proto_write(&mut b);
+
+            let mut view = b.view();
+            assert_eq!(vec![1.5f32, -2.0], Vec::<f32>::borrow_read(&mut view));
+            assert_eq!(vec![1u32, 70000], Vec::<u32>::borrow_read(&mut view));
+        }
+
+        let mut view = BufferView::new(&[0, 0, 0, 0, 0, 0, 0, 1, 0xff]);
+        assert_eq!(Err(DecodeError::InvalidUtf8 { offset: 8 }), <&str>::try_borrow_read(&mut view));
+
+        let mut view = BufferView::new(&[2, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Ok(Value::Unknown), Value::try_borrow_read(&mut view));
+    }
+
+    #[test]
+    fn tuple_and_unit_structs() {
+        let mut b = Buffer::new();
+
+        Marker.proto_write(&mut b);
+        assert!(b.is_empty());
+
+        UserId(7).proto_write(&mut b);
+        assert_eq!(&[0, 0, 0, 0, 0, 0, 0, 7], b.as_slice());
+
+        Point(1, -1, String::from("Den")).proto_write(&mut b);
+        Marker.proto_write(&mut b);
+
+        assert_eq!(UserId(7), UserId::proto_read(&mut b));
+        assert_eq!(Point(1, -1, String::from("Den")), Point::proto_read(&mut b));
+        assert_eq!(Marker, Marker::proto_read(&mut b));
+        assert_eq!(0, b.remaining());
+
+        let mut b = Buffer::from_vec(vec![0]);
+
+        assert_eq!(
+            Err(DecodeError::Uninhabited { offset: 0, name: "Never" }),
+            Never::try_proto_read(&mut b)
+        );
+        assert_eq!(1, b.remaining());
+    }
+
+    #[test]
+    fn generics() {
+        let mut b = Buffer::new();
+
+        let page = Page { items: vec![UserId(1), UserId(2)], total: 10 };
+        let either: Vec<Either<u8, String>> = vec![Either::Left(1), Either::Right { value: String::from("Den") }];
+        let pair = Pair { first: 'a', second: 'b' };
+
+        page.proto_write(&mut b);
+        either.proto_write(&mut b);
+        pair.proto_write(&mut b);
+
+        assert_eq!(page, Page::proto_read(&mut b));
+        assert_eq!(either, Vec::<Either<u8, String>>::proto_read(&mut b));
+        assert_eq!(pair, Pair::proto_read(&mut b));
+
+        let mut b = Buffer::new();
+        let name = String::from("Den");
+
+        Named { name: &name, value: 1u8 }.proto_write(&mut b);
+
+        assert_eq!("Den", String::proto_read(&mut b));
+        assert_eq!(1, u8::proto_read(&mut b));
+
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Protobuf;
+
+        let batch = Batch { items: vec![Test1 { a: 1 }, Test1 { a: 2 }], total: 2 };
+        batch.proto_write(&mut b);
+
+        assert_eq!(batch, Batch::proto_read(&mut b));
+    }
+
+    #[test]
+    fn protobuf_wire_format() {
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Protobuf;
+
+        protobuf::try_write_message(&Test1 { a: 150 }, &mut b).unwrap();
+        assert_eq!(&[0x08, 0x96, 0x01], b.as_slice());
+
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Protobuf;
+
+        protobuf::try_write_message(&Test3 { c: Test1 { a: 150 } }, &mut b).unwrap();
+        assert_eq!(&[0x1a, 0x03, 0x08, 0x96, 0x01], b.as_slice());
+        assert_eq!(Ok(Test3 { c: Test1 { a: 150 } }), protobuf::try_read_message(&mut b));
+
+        let mut out = [0u8; 5];
+        let mut sink = SliceSink::new(&mut out);
+        sink.encoding = Encoding::Protobuf;
+
+        protobuf::try_write_message(&Test3 { c: Test1 { a: 150 } }, &mut sink).unwrap();
+        assert_eq!(b.as_slice(), sink.as_slice());
+
+        let mut b = Buffer::from_vec(vec![0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        b.encoding = Encoding::Protobuf;
+
+        assert_eq!(Ok(Test1 { a: -1 }), protobuf::try_read_message(&mut b));
+
+        // anywhere but the top level a message is length-delimited
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Protobuf;
+
+        Test1 { a: 150 }.proto_write(&mut b);
+        assert_eq!(&[0x03, 0x08, 0x96, 0x01], b.as_slice());
+
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Protobuf;
+
+        let tests = vec![Test1 { a: 1 }, Test1 { a: 2 }];
+        tests.proto_write(&mut b);
+        assert_eq!(&[0x02, 0x02, 0x08, 0x01, 0x02, 0x08, 0x02], b.as_slice());
+        assert_eq!(tests, Vec::<Test1>::proto_read(&mut b));
+
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Protobuf;
+
+        let outer = Outer { x: 1, t: Test1 { a: 150 }, y: 2 };
+        outer.proto_write(&mut b);
+        assert_eq!(&[0x01, 0x03, 0x08, 0x96, 0x01, 0x02], b.as_slice());
+        assert_eq!(outer, Outer::proto_read(&mut b));
+
+        // the lengths of messages and packed fields are varints whatever the sink settings are
+        let test4 = Test4 { c: Test1 { a: 150 }, d: vec![3, 270, 86942] };
+        let expected = [0x1a, 0x03, 0x08, 0x96, 0x01, 0x22, 0x06, 0x03, 0x8e, 0x02, 0x9e, 0xa7, 0x05];
+
+        let mut b = Buffer::new();
+        protobuf::try_write_message(&test4, &mut b).unwrap();
+        assert_eq!(&expected, b.as_slice());
+
+        let mut out = [0u8; 13];
+        let mut sink = SliceSink::new(&mut out);
+
+        protobuf::try_write_message(&test4, &mut sink).unwrap();
+        assert_eq!(&expected, sink.as_slice());
+        assert_eq!(Ok(test4), protobuf::try_read_message(&mut b));
+
+        // and not capped by len_width
+        let long = Test4 { c: Test1 { a: 1 }, d: vec![-1; 7000] };
+
+        let mut b = Buffer::new();
+        b.len_width = LenWidth::U16;
+
+        protobuf::try_write_message(&long, &mut b).unwrap();
+        assert_eq!(&[0x22, 0xf0, 0xa2, 0x04], &b.as_slice()[4..8]);
+        assert_eq!(Ok(long), protobuf::try_read_message(&mut b));
+    }
+
+    #[test]
+    fn protobuf_message() {
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Protobuf;
+
+        account().proto_write(&mut b);
+
+        // packed repeated int32 scores = 6
+        let packed = [0x32, 0x0d, 0x03, 0x8e, 0x02, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert!(b.as_slice().windows(packed.len()).any(|w| w == packed));
+
+        assert_eq!(account(), Account::proto_read(&mut b));
+
+        b.pos = 0;
+        assert_eq!(AccountName { name: String::from("Den") }, AccountName::proto_read(&mut b));
+
+        let mut b = Buffer::new();
+
+        account().proto_write(&mut b);
+        assert_eq!(account(), Account::proto_read(&mut b));
+    }
+
+    #[test]
+    fn io_adapters() {
+        let contact = ContactV2 {
+            name: String::from("Den"),
+            age: 37,
+            email: String::from("den@mail.com"),
+            tags: vec![String::from("admin")]
+        };
+
+        let mut sink = IoSink::new(Vec::new());
+        sink.encoding = Encoding::Varint;
+        contact.proto_write(&mut sink);
+        let data = sink.into_inner().unwrap();
+
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Varint;
+        contact.proto_write(&mut b);
+        assert_eq!(b.as_slice(), &data[..]);
+
+        let mut src = IoSource::new(&data[..]);
+        src.encoding = Encoding::Varint;
+        assert_eq!(
+            ContactV3 { name: String::from("Den"), tags: vec![String::from("admin")] },
+            ContactV3::proto_read(&mut src)
+        );
+
+        // a top level protobuf message runs to the end of the stream
+        let mut sink = IoSink::new(Vec::new());
+        sink.encoding = Encoding::Protobuf;
+        protobuf::try_write_message(&account(), &mut sink).unwrap();
+        let data = sink.into_inner().unwrap();
+
+        let mut src = IoSource::new(std::io::Cursor::new(data));
+        src.encoding = Encoding::Protobuf;
+        assert_eq!(Ok(account()), protobuf::try_read_message(&mut src));
+
+        let mut sink = IoSink::new(Vec::new());
+        protobuf::try_write_message(&Test4 { c: Test1 { a: 150 }, d: vec![3] }, &mut sink).unwrap();
+        assert_eq!(vec![0x1a, 0x03, 0x08, 0x96, 0x01, 0x22, 0x01, 0x03], sink.into_inner().unwrap());
+    }
+
+    #[test]
+    fn protobuf_bytes() {
+        let blob = Blob { data: protobuf::Bytes(vec![0x80, 0xff]), codes: vec![0x80] };
+
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Protobuf;
+
+        protobuf::try_write_message(&blob, &mut b).unwrap();
+        assert_eq!(&[0x0a, 0x02, 0x80, 0xff, 0x12, 0x02, 0x80, 0x01], b.as_slice());
+        assert_eq!(Ok(blob), protobuf::try_read_message(&mut b));
+
+        let mut b = Buffer::new();
+        let data = protobuf::Bytes::from(vec![1, 2, 3]);
+
+        data.proto_write(&mut b);
+        assert_eq!(vec![1u8, 2, 3], Vec::<u8>::proto_read(&mut b));
+    }
+
+    #[test]
+    fn protobuf_errors() {
+        let mut b = Buffer::from_vec(vec![0x0b, 0x00]);
+        b.encoding = Encoding::Protobuf;
+
+        assert_eq!(Err(DecodeError::InvalidWireType { offset: 0, wire_type: 3 }), protobuf::try_read_message::<Test1, _>(&mut b));
+
+        let mut b = Buffer::from_vec(vec![0x0d, 0x00, 0x00, 0x00, 0x00]);
+        b.encoding = Encoding::Protobuf;
+
+        assert_eq!(Err(DecodeError::InvalidWireType { offset: 1, wire_type: 5 }), protobuf::try_read_message::<Test1, _>(&mut b));
+
+        let mut b = Buffer::from_vec(vec![0x1a, 0x02, 0x08, 0x96, 0x01]);
+        b.encoding = Encoding::Protobuf;
+
+        assert_eq!(Err(DecodeError::InvalidLength { offset: 1, len: 2 }), protobuf::try_read_message::<Test3, _>(&mut b));
+    }
+
+    #[test]
+    fn field_attrs() {
+        let mut b = Buffer::new();
+
+        let session = Session {
+            user: User { name: String::from("Den"), email: String::from("den@mail.com"), age: 30 },
+            cache: vec![1, 2, 3],
+            retries: 10,
+            timeout: std::time::Duration::from_millis(1500)
+        };
+        session.proto_write(&mut b);
+
+        let mut expected = Buffer::new();
+        session.user.proto_write(&mut expected);
+        1500u64.proto_write(&mut expected);
+
+        assert_eq!(expected.as_slice(), b.as_slice());
+
+        assert_eq!(
+            Session { cache: Vec::new(), retries: 3, ..session },
+            Session::proto_read(&mut b)
+        );
+
+        let mut b = Buffer::new();
+        let jobs = vec![
+            Job::Run { name: String::from("build"), started: true },
+            Job::Wait(std::time::Duration::from_millis(20))
+        ];
+        jobs.proto_write(&mut b);
+
+        assert_eq!(
+            vec![Job::Run { name: String::from("build"), started: false }, Job::Wait(std::time::Duration::from_millis(20))],
+            Vec::<Job>::proto_read(&mut b)
+        );
+
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Protobuf;
+
+        protobuf::try_write_message(&CachedAccount { id: 1, retries: 10, name: String::from("Den") }, &mut b).unwrap();
+        assert_eq!(&[0x08, 0x01, 0x12, 0x03, b'D', b'e', b'n'], b.as_slice());
+        assert_eq!(Ok(CachedAccount { id: 1, retries: 3, name: String::from("Den") }), protobuf::try_read_message(&mut b));
+
+        let mut b = Buffer::from_vec(vec![0x08, 0x01]);
+        b.encoding = Encoding::Protobuf;
+
+        assert_eq!(Ok(CachedAccount { id: 1, retries: 3, name: default_name() }), protobuf::try_read_message(&mut b));
+    }
+
+    #[test]
+    fn decode_errors() {
+        let mut b = Buffer::new();
+
+        3u8.proto_write(&mut b);
+
+        assert_eq!(
+            Err(DecodeError::UnknownVariant { offset: 0, name: "UserStatus", variant: 3 }),
+            UserStatus::try_proto_read(&mut b)
+        );
+
+        // only the name of a user
+        let mut b = Buffer::new();
+        String::from("Den").proto_write(&mut b);
+
+        assert!(matches!(User::try_proto_read(&mut b), Err(DecodeError::UnexpectedEof { offset: 11, .. })));
+    }
 }
 
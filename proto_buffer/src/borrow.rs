@@ -0,0 +1,161 @@
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use super::{Buffer, DecodeError, Encoding, Endian, LenWidth, ProtoSource};
+
+// a read-only cursor over borrowed bytes, so strings and byte slices can be decoded without copying
+#[derive(Debug, Clone)]
+pub struct BufferView<'a> {
+    data: &'a [u8],
+    pub pos: usize,
+    pub endian: Endian,
+    pub encoding: Encoding,
+    pub len_width: LenWidth,
+}
+
+pub trait ProtoBorrowRead<'a>: Sized {
+    fn try_borrow_read(buf: &mut BufferView<'a>) -> Result<Self, DecodeError>;
+
+    // the numeric types read their elements in bulk, like ProtoReader::try_proto_read_vec
+    fn try_borrow_read_vec(len: usize, buf: &mut BufferView<'a>) -> Result<Vec<Self>, DecodeError> {
+        let mut v = Vec::with_capacity(len.min(buf.remaining()));
+
+        for _i in 0..len {
+            v.push(Self::try_borrow_read(buf)?)
+        }
+
+        Ok(v)
+    }
+
+    fn borrow_read(buf: &mut BufferView<'a>) -> Self {
+        match Self::try_borrow_read(buf) {
+            Ok(v) => v,
+            Err(e) => panic!("{}", e)
+        }
+    }
+}
+
+impl Buffer {
+    // starts at the current position and uses the same settings
+    pub fn view(&self) -> BufferView<'_> {
+        BufferView {
+            data: self.as_slice(),
+            pos: self.pos,
+            endian: self.endian,
+            encoding: self.encoding,
+            len_width: self.len_width
+        }
+    }
+}
+
+impl<'a> BufferView<'a> {
+    pub fn new(data: &'a [u8]) -> BufferView<'a> {
+        BufferView {
+            data,
+            pos: 0,
+            endian: Endian::BigEndian,
+            encoding: Encoding::Fixed,
+            len_width: LenWidth::U64
+        }
+    }
+
+    pub fn as_slice(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(super::read_slice(self.data, &mut self.pos, 1)?[0])
+    }
+
+    pub(crate) fn read_slice_u8(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        super::read_slice(self.data, &mut self.pos, len)
+    }
+
+    pub(crate) fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        super::read_varint(self.data, &mut self.pos)
+    }
+
+    pub fn try_read_bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.try_read_len()?;
+
+        self.read_slice_u8(len)
+    }
+
+    pub fn try_read_utf8(&mut self) -> Result<&'a str, DecodeError> {
+        let len = self.try_read_len()?;
+        let offset = self.pos;
+
+        core::str::from_utf8(self.read_slice_u8(len)?).map_err(|_| DecodeError::InvalidUtf8 { offset })
+    }
+}
+
+impl<'a> ProtoBorrowRead<'a> for char {
+    fn try_borrow_read(buf: &mut BufferView<'a>) -> Result<Self, DecodeError> {
+        let offset = buf.pos;
+        let value = u32::try_borrow_read(buf)?;
+
+        core::char::from_u32(value).ok_or(DecodeError::InvalidChar { offset, value })
+    }
+}
+
+impl<'de: 'a, 'a> ProtoBorrowRead<'de> for &'a str {
+    fn try_borrow_read(buf: &mut BufferView<'de>) -> Result<Self, DecodeError> {
+        buf.try_read_utf8()
+    }
+}
+
+// same layout as Vec<u8>
+impl<'de: 'a, 'a> ProtoBorrowRead<'de> for &'a [u8] {
+    fn try_borrow_read(buf: &mut BufferView<'de>) -> Result<Self, DecodeError> {
+        buf.try_read_bytes()
+    }
+}
+
+impl<'de: 'a, 'a> ProtoBorrowRead<'de> for Cow<'a, str> {
+    fn try_borrow_read(buf: &mut BufferView<'de>) -> Result<Self, DecodeError> {
+        Ok(Cow::Borrowed(buf.try_read_utf8()?))
+    }
+}
+
+impl<'de: 'a, 'a> ProtoBorrowRead<'de> for Cow<'a, [u8]> {
+    fn try_borrow_read(buf: &mut BufferView<'de>) -> Result<Self, DecodeError> {
+        Ok(Cow::Borrowed(buf.try_read_bytes()?))
+    }
+}
+
+impl<'a, T:ProtoBorrowRead<'a>> ProtoBorrowRead<'a> for Option<T> {
+    fn try_borrow_read(buf: &mut BufferView<'a>) -> Result<Self, DecodeError> {
+        let offset = buf.pos;
+
+        match buf.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::try_borrow_read(buf)?)),
+            tag => Err(DecodeError::InvalidTag { offset, tag })
+        }
+    }
+}
+
+impl<'a, T:ProtoBorrowRead<'a>> ProtoBorrowRead<'a> for Vec<T> {
+    fn try_borrow_read(buf: &mut BufferView<'a>) -> Result<Self, DecodeError> {
+        let len = buf.try_read_len()?;
+        T::try_borrow_read_vec(len, buf)
+    }
+}
+
+impl<'a, T:ProtoBorrowRead<'a>> ProtoBorrowRead<'a> for Box<T> {
+    fn try_borrow_read(buf: &mut BufferView<'a>) -> Result<Self, DecodeError> {
+        Ok(Box::new(T::try_borrow_read(buf)?))
+    }
+}
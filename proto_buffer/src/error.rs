@@ -0,0 +1,169 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+use super::LenWidth;
+
+// non_exhaustive because the Io variants come and go with the std feature
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum DecodeError {
+    UnexpectedEof { offset: usize, needed: usize },
+    InvalidTag { offset: usize, tag: u8 },
+    InvalidUtf8 { offset: usize },
+    InvalidChar { offset: usize, value: u32 },
+    InvalidVarint { offset: usize },
+    LengthOverflow { offset: usize, len: u64 },
+    InvalidLength { offset: usize, len: usize },
+    InvalidKey { offset: usize, key: u64 },
+    InvalidWireType { offset: usize, wire_type: u8 },
+    UnknownVariant { offset: usize, name: &'static str, variant: u64 },
+    UnknownVersion { offset: usize, name: &'static str, version: u32 },
+    InvalidNonZero { offset: usize },
+    Uninhabited { offset: usize, name: &'static str },
+    #[cfg(feature = "std")]
+    Io { offset: usize, kind: io::ErrorKind },
+}
+
+impl DecodeError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            DecodeError::UnexpectedEof { offset, .. } |
+            DecodeError::InvalidTag { offset, .. } |
+            DecodeError::InvalidUtf8 { offset } |
+            DecodeError::InvalidChar { offset, .. } |
+            DecodeError::InvalidVarint { offset } |
+            DecodeError::LengthOverflow { offset, .. } |
+            DecodeError::InvalidLength { offset, .. } |
+            DecodeError::InvalidKey { offset, .. } |
+            DecodeError::InvalidWireType { offset, .. } |
+            DecodeError::UnknownVariant { offset, .. } |
+            DecodeError::UnknownVersion { offset, .. } |
+            DecodeError::InvalidNonZero { offset } |
+            DecodeError::Uninhabited { offset, .. } => offset,
+            #[cfg(feature = "std")]
+            DecodeError::Io { offset, .. } => offset
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof { offset, needed } => {
+                write!(f, "unexpected end of buffer at {}: {} more bytes needed", offset, needed)
+            }
+            DecodeError::InvalidTag { offset, tag } => {
+                write!(f, "invalid tag {} at {}", tag, offset)
+            }
+            DecodeError::InvalidUtf8 { offset } => {
+                write!(f, "invalid utf-8 string at {}", offset)
+            }
+            DecodeError::InvalidChar { offset, value } => {
+                write!(f, "invalid char {:#x} at {}", value, offset)
+            }
+            DecodeError::InvalidVarint { offset } => {
+                write!(f, "invalid varint at {}", offset)
+            }
+            DecodeError::LengthOverflow { offset, len } => {
+                write!(f, "length {} at {} does not fit into usize", len, offset)
+            }
+            DecodeError::InvalidLength { offset, len } => {
+                write!(f, "length {} at {} does not match the payload", len, offset)
+            }
+            DecodeError::InvalidKey { offset, key } => {
+                write!(f, "invalid field key {} at {}", key, offset)
+            }
+            DecodeError::InvalidWireType { offset, wire_type } => {
+                write!(f, "unexpected wire type {} at {}", wire_type, offset)
+            }
+            DecodeError::UnknownVariant { offset, name, variant } => {
+                write!(f, "unknown variant {} of {} at {}", variant, name, offset)
+            }
+            DecodeError::UnknownVersion { offset, name, version } => {
+                write!(f, "unknown version {} of {} at {}", version, name, offset)
+            }
+            DecodeError::InvalidNonZero { offset } => {
+                write!(f, "zero value for a non-zero integer at {}", offset)
+            }
+            DecodeError::Uninhabited { offset, name } => {
+                write!(f, "{} has no values to read at {}", name, offset)
+            }
+            #[cfg(feature = "std")]
+            DecodeError::Io { offset, kind } => {
+                write!(f, "io error at {}: {:?}", offset, kind)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum EncodeError {
+    LengthOverflow { offset: usize, len: u64, len_width: LenWidth },
+    OutOfBounds { offset: usize, len: usize },
+    NotAppending { offset: usize, len: usize },
+    InsufficientSpace { offset: usize, needed: usize },
+    UnfinishedLenPrefix { offset: usize },
+    #[cfg(feature = "std")]
+    Io { offset: usize, kind: io::ErrorKind },
+}
+
+impl EncodeError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            EncodeError::LengthOverflow { offset, .. } |
+            EncodeError::OutOfBounds { offset, .. } |
+            EncodeError::NotAppending { offset, .. } |
+            EncodeError::InsufficientSpace { offset, .. } |
+            EncodeError::UnfinishedLenPrefix { offset } => offset,
+            #[cfg(feature = "std")]
+            EncodeError::Io { offset, .. } => offset
+        }
+    }
+
+    // for errors from a value encoded on its own before it was copied to `by`
+    pub(crate) fn offset_by(self, by: usize) -> EncodeError {
+        match self {
+            EncodeError::LengthOverflow { offset, len, len_width } => EncodeError::LengthOverflow { offset: offset + by, len, len_width },
+            EncodeError::OutOfBounds { offset, len } => EncodeError::OutOfBounds { offset: offset + by, len },
+            EncodeError::NotAppending { offset, len } => EncodeError::NotAppending { offset: offset + by, len },
+            EncodeError::InsufficientSpace { offset, needed } => EncodeError::InsufficientSpace { offset: offset + by, needed },
+            EncodeError::UnfinishedLenPrefix { offset } => EncodeError::UnfinishedLenPrefix { offset: offset + by },
+            #[cfg(feature = "std")]
+            EncodeError::Io { offset, kind } => EncodeError::Io { offset: offset + by, kind }
+        }
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::LengthOverflow { offset, len, len_width } => {
+                write!(f, "length {} at {} does not fit into {:?} prefix", len, offset, len_width)
+            }
+            EncodeError::OutOfBounds { offset, len } => {
+                write!(f, "write at {} is past the end of the buffer ({} bytes)", offset, len)
+            }
+            EncodeError::NotAppending { offset, len } => {
+                write!(f, "write at {} in append-only mode, the buffer ends at {}", offset, len)
+            }
+            EncodeError::InsufficientSpace { offset, needed } => {
+                write!(f, "no space left at {}: {} more bytes needed", offset, needed)
+            }
+            EncodeError::UnfinishedLenPrefix { offset } => {
+                write!(f, "length prefix at {} was never patched", offset)
+            }
+            #[cfg(feature = "std")]
+            EncodeError::Io { offset, kind } => {
+                write!(f, "io error at {}: {:?}", offset, kind)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EncodeError {}
@@ -0,0 +1,309 @@
+use alloc::borrow::{Cow, ToOwned};
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use core::convert::TryInto;
+use core::marker::PhantomData;
+use core::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize,
+    NonZeroU128, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize, Wrapping
+};
+#[cfg(feature = "std")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher, Hash};
+
+use super::{DecodeError, EncodeError, ProtoReader, ProtoSink, ProtoSource, ProtoWriter};
+
+impl<T:ProtoWriter> ProtoWriter for VecDeque<T> {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_len(self.len())?;
+
+        for el in self.iter() {
+            el.try_proto_write(buf)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T:ProtoReader> ProtoReader for VecDeque<T> {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let len = buf.try_read_len()?;
+        let mut v = VecDeque::with_capacity(len.min(buf.available()));
+
+        for _i in 0..len {
+            v.push_back(T::try_proto_read(buf)?)
+        }
+
+        Ok(v)
+    }
+}
+
+// HashMap and HashSet are only in std
+#[cfg(feature = "std")]
+impl<T:ProtoWriter, H> ProtoWriter for HashSet<T, H> {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_len(self.len())?;
+
+        for el in self.iter() {
+            el.try_proto_write(buf)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, H> ProtoReader for HashSet<T, H> where T: ProtoReader + Eq + Hash, H: BuildHasher + Default {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let len = buf.try_read_len()?;
+        let mut v = HashSet::with_capacity_and_hasher(len.min(buf.available()), H::default());
+
+        for _i in 0..len {
+            v.insert(T::try_proto_read(buf)?);
+        }
+
+        Ok(v)
+    }
+}
+
+impl<T:ProtoWriter> ProtoWriter for BTreeSet<T> {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_len(self.len())?;
+
+        for el in self.iter() {
+            el.try_proto_write(buf)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T:ProtoReader + Ord> ProtoReader for BTreeSet<T> {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let len = buf.try_read_len()?;
+        let mut v = BTreeSet::new();
+
+        for _i in 0..len {
+            v.insert(T::try_proto_read(buf)?);
+        }
+
+        Ok(v)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K:ProtoWriter, V:ProtoWriter, H> ProtoWriter for HashMap<K, V, H> {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_len(self.len())?;
+
+        for (k, v) in self.iter() {
+            k.try_proto_write(buf)?;
+            v.try_proto_write(buf)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V, H> ProtoReader for HashMap<K, V, H> where K: ProtoReader + Eq + Hash, V: ProtoReader, H: BuildHasher + Default {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let len = buf.try_read_len()?;
+        let mut m = HashMap::with_capacity_and_hasher(len.min(buf.available()), H::default());
+
+        for _i in 0..len {
+            let k = K::try_proto_read(buf)?;
+            m.insert(k, V::try_proto_read(buf)?);
+        }
+
+        Ok(m)
+    }
+}
+
+impl<K:ProtoWriter, V:ProtoWriter> ProtoWriter for BTreeMap<K, V> {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_len(self.len())?;
+
+        for (k, v) in self.iter() {
+            k.try_proto_write(buf)?;
+            v.try_proto_write(buf)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<K:ProtoReader + Ord, V:ProtoReader> ProtoReader for BTreeMap<K, V> {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let len = buf.try_read_len()?;
+        let mut m = BTreeMap::new();
+
+        for _i in 0..len {
+            let k = K::try_proto_read(buf)?;
+            m.insert(k, V::try_proto_read(buf)?);
+        }
+
+        Ok(m)
+    }
+}
+
+// arrays have a fixed size, so no length is written
+impl<T:ProtoWriter, const N: usize> ProtoWriter for [T; N] {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        T::try_proto_write_slice(self, buf)
+    }
+}
+
+impl<T:ProtoReader, const N: usize> ProtoReader for [T; N] {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        match T::try_proto_read_vec(N, buf)?.try_into() {
+            Ok(a) => Ok(a),
+            Err(_) => unreachable!()
+        }
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident . $idx:tt), +) => {
+        impl<$($name:ProtoWriter), +> ProtoWriter for ($($name,)+) {
+            fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+                $(self.$idx.try_proto_write(buf)?;)+
+
+                Ok(())
+            }
+        }
+
+        impl<$($name:ProtoReader), +> ProtoReader for ($($name,)+) {
+            fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+                Ok(($($name::try_proto_read(buf)?,)+))
+            }
+        }
+    }
+}
+
+impl_tuple! (A.0);
+impl_tuple! (A.0, B.1);
+impl_tuple! (A.0, B.1, C.2);
+impl_tuple! (A.0, B.1, C.2, D.3);
+impl_tuple! (A.0, B.1, C.2, D.3, E.4);
+impl_tuple! (A.0, B.1, C.2, D.3, E.4, F.5);
+impl_tuple! (A.0, B.1, C.2, D.3, E.4, F.5, G.6);
+impl_tuple! (A.0, B.1, C.2, D.3, E.4, F.5, G.6, H.7);
+impl_tuple! (A.0, B.1, C.2, D.3, E.4, F.5, G.6, H.7, I.8);
+impl_tuple! (A.0, B.1, C.2, D.3, E.4, F.5, G.6, H.7, I.8, J.9);
+impl_tuple! (A.0, B.1, C.2, D.3, E.4, F.5, G.6, H.7, I.8, J.9, K.10);
+impl_tuple! (A.0, B.1, C.2, D.3, E.4, F.5, G.6, H.7, I.8, J.9, K.10, L.11);
+
+impl<T:ProtoWriter + ?Sized> ProtoWriter for Rc<T> {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        (**self).try_proto_write(buf)
+    }
+}
+
+impl<T:ProtoReader> ProtoReader for Rc<T> {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        Ok(Rc::new(T::try_proto_read(buf)?))
+    }
+}
+
+impl<T:ProtoWriter + ?Sized> ProtoWriter for Arc<T> {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        (**self).try_proto_write(buf)
+    }
+}
+
+impl<T:ProtoReader> ProtoReader for Arc<T> {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        Ok(Arc::new(T::try_proto_read(buf)?))
+    }
+}
+
+impl<'a, T:ProtoWriter + ToOwned + ?Sized> ProtoWriter for Cow<'a, T> {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        (**self).try_proto_write(buf)
+    }
+}
+
+// always reads into an owned value
+impl<'a, T:ToOwned + ?Sized> ProtoReader for Cow<'a, T> where T::Owned: ProtoReader {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        Ok(Cow::Owned(T::Owned::try_proto_read(buf)?))
+    }
+}
+
+impl<T:ProtoWriter, E:ProtoWriter> ProtoWriter for Result<T, E> {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        match self {
+            Ok(v) => {
+                0u8.try_proto_write(buf)?;
+                v.try_proto_write(buf)
+            },
+            Err(e) => {
+                1u8.try_proto_write(buf)?;
+                e.try_proto_write(buf)
+            }
+        }
+    }
+}
+
+impl<T:ProtoReader, E:ProtoReader> ProtoReader for Result<T, E> {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let offset = buf.pos();
+
+        match buf.try_read_u8()? {
+            0 => Ok(Ok(T::try_proto_read(buf)?)),
+            1 => Ok(Err(E::try_proto_read(buf)?)),
+            tag => Err(DecodeError::InvalidTag { offset, tag })
+        }
+    }
+}
+
+impl<T:ProtoWriter> ProtoWriter for Wrapping<T> {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        self.0.try_proto_write(buf)
+    }
+}
+
+impl<T:ProtoReader> ProtoReader for Wrapping<T> {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        Ok(Wrapping(T::try_proto_read(buf)?))
+    }
+}
+
+impl<T:?Sized> ProtoWriter for PhantomData<T> {
+    fn try_proto_write<S: ProtoSink>(&self, _buf: &mut S) -> Result<(), EncodeError> {
+        Ok(())
+    }
+}
+
+impl<T:?Sized> ProtoReader for PhantomData<T> {
+    fn try_proto_read<S: ProtoSource>(_buf: &mut S) -> Result<Self, DecodeError> {
+        Ok(PhantomData)
+    }
+}
+
+// non-zero integers use the layout of the underlying integer
+macro_rules! impl_non_zero {
+    ($($t:ty => $int:ty), +) => {
+        $(impl ProtoWriter for $t {
+            fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+                self.get().try_proto_write(buf)
+            }
+        }
+
+        impl ProtoReader for $t {
+            fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+                let offset = buf.pos();
+
+                <$t>::new(<$int>::try_proto_read(buf)?).ok_or(DecodeError::InvalidNonZero { offset })
+            }
+        })*
+    }
+}
+
+impl_non_zero! (
+    NonZeroU8 => u8, NonZeroU16 => u16, NonZeroU32 => u32, NonZeroU64 => u64, NonZeroU128 => u128, NonZeroUsize => usize,
+    NonZeroI8 => i8, NonZeroI16 => i16, NonZeroI32 => i32, NonZeroI64 => i64, NonZeroI128 => i128, NonZeroIsize => isize
+);
@@ -0,0 +1,257 @@
+use std::io::{self, Read, Write};
+
+use super::{DecodeError, EncodeError, Encoding, Endian, LenPrefix, LenWidth, ProtoSink, ProtoSource};
+
+const CHUNK: usize = 8 * 1024;
+
+// encodes into an io::Write through an internal buffer, like a BufWriter that can back-patch lengths.
+// Bytes after an unfinished length prefix stay buffered until the prefix is patched or aborted.
+pub struct IoSink<W: Write> {
+    inner: Option<W>,
+    buf: Vec<u8>,
+    flushed: usize,
+    open_prefixes: usize,
+    outer_prefix: usize,
+    pub endian: Endian,
+    pub encoding: Encoding,
+    pub len_width: LenWidth,
+}
+
+impl<W: Write> IoSink<W> {
+    pub fn new(inner: W) -> IoSink<W> {
+        IoSink {
+            inner: Some(inner),
+            buf: Vec::with_capacity(CHUNK),
+            flushed: 0,
+            open_prefixes: 0,
+            outer_prefix: 0,
+            endian: Endian::BigEndian,
+            encoding: Encoding::Fixed,
+            len_width: LenWidth::U64
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    // hands the buffered bytes to the writer and flushes it, fails while a length prefix is open
+    pub fn flush(&mut self) -> Result<(), EncodeError> {
+        if self.open_prefixes > 0 {
+            return Err(EncodeError::UnfinishedLenPrefix { offset: self.outer_prefix });
+        }
+
+        self.flush_buf()?;
+
+        let offset = self.write_pos();
+        self.inner.as_mut().unwrap().flush().map_err(|e| EncodeError::Io { offset, kind: e.kind() })
+    }
+
+    pub fn into_inner(mut self) -> Result<W, EncodeError> {
+        self.flush()?;
+
+        Ok(self.inner.take().unwrap())
+    }
+
+    fn open_prefix(&mut self, varint: bool) -> Result<LenPrefix, EncodeError> {
+        // the placeholder must not reach the writer before it is patched
+        let start = self.write_pos();
+
+        if self.open_prefixes == 0 {
+            self.outer_prefix = start;
+        }
+
+        self.open_prefixes += 1;
+
+        let placeholder = if varint { self.try_write_u8(0) } else { self.try_write_len(0) };
+
+        if let Err(e) = placeholder {
+            self.open_prefixes -= 1;
+            return Err(e);
+        }
+
+        Ok(LenPrefix { start, payload_start: self.write_pos(), varint })
+    }
+
+    fn flush_buf(&mut self) -> Result<(), EncodeError> {
+        if self.open_prefixes > 0 || self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let offset = self.flushed;
+        self.inner.as_mut().unwrap().write_all(&self.buf).map_err(|e| EncodeError::Io { offset, kind: e.kind() })?;
+
+        self.flushed += self.buf.len();
+        self.buf.clear();
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for IoSink<W> {
+    fn drop(&mut self) {
+        // errors can not be reported here, call flush or into_inner to see them.
+        // Nothing is written while a length prefix is open
+        if self.inner.is_some() {
+            let _ = self.flush_buf();
+        }
+    }
+}
+
+impl<W: Write> ProtoSink for IoSink<W> {
+    fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn len_width(&self) -> LenWidth {
+        self.len_width
+    }
+
+    fn write_pos(&self) -> usize {
+        self.flushed + self.buf.len()
+    }
+
+    fn try_write_bytes(&mut self, v: &[u8]) -> Result<(), EncodeError> {
+        self.buf.extend_from_slice(v);
+
+        if self.buf.len() >= CHUNK {
+            self.flush_buf()?;
+        }
+
+        Ok(())
+    }
+
+    fn begin_len_prefix(&mut self) -> Result<LenPrefix, EncodeError> {
+        self.open_prefix(false)
+    }
+
+    fn begin_varint_len_prefix(&mut self) -> Result<LenPrefix, EncodeError> {
+        self.open_prefix(true)
+    }
+
+    fn patch_len(&mut self, prefix: LenPrefix, len: usize) -> Result<(), EncodeError> {
+        let mut buf = std::mem::take(&mut self.buf);
+        let patched = super::stream::patch_len_in(self, &mut buf, self.flushed, &prefix, len);
+        self.buf = buf;
+
+        self.open_prefixes -= 1;
+        patched?;
+
+        if self.buf.len() >= CHUNK {
+            self.flush_buf()?;
+        }
+
+        Ok(())
+    }
+
+    // everything from the prefix on is still buffered, so the partial value is dropped
+    fn abort_len_prefix(&mut self, prefix: LenPrefix) {
+        self.buf.truncate(prefix.start - self.flushed);
+        self.open_prefixes -= 1;
+    }
+}
+
+// decodes from an io::Read, reading ahead in chunks
+pub struct IoSource<R: Read> {
+    inner: R,
+    buf: Vec<u8>,
+    start: usize,
+    pos: usize,
+    pub endian: Endian,
+    pub encoding: Encoding,
+    pub len_width: LenWidth,
+}
+
+impl<R: Read> IoSource<R> {
+    pub fn new(inner: R) -> IoSource<R> {
+        IoSource {
+            inner,
+            buf: Vec::with_capacity(CHUNK),
+            start: 0,
+            pos: 0,
+            endian: Endian::BigEndian,
+            encoding: Encoding::Fixed,
+            len_width: LenWidth::U64
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    // reads until `len` bytes are buffered, growing a chunk at a time so a broken length can not
+    // allocate more than the stream actually holds
+    fn fill(&mut self, len: usize) -> Result<(), DecodeError> {
+        if self.buf.len() - self.start >= len {
+            return Ok(());
+        }
+
+        self.buf.drain(..self.start);
+        self.start = 0;
+
+        while self.buf.len() < len {
+            let filled = self.buf.len();
+            self.buf.resize(filled + CHUNK, 0);
+
+            match self.inner.read(&mut self.buf[filled..]) {
+                Ok(0) => {
+                    self.buf.truncate(filled);
+                    return Err(DecodeError::UnexpectedEof { offset: self.pos, needed: len - filled });
+                },
+                Ok(n) => self.buf.truncate(filled + n),
+                Err(e) => {
+                    self.buf.truncate(filled);
+
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(DecodeError::Io { offset: self.pos, kind: e.kind() });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> ProtoSource for IoSource<R> {
+    fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn len_width(&self) -> LenWidth {
+        self.len_width
+    }
+
+    fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn available(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
+    fn try_read_exact(&mut self, len: usize) -> Result<&[u8], DecodeError> {
+        self.fill(len)?;
+
+        let start = self.start;
+        self.start += len;
+        self.pos += len;
+
+        Ok(&self.buf[start..start + len])
+    }
+
+    fn try_remaining(&mut self) -> Result<usize, DecodeError> {
+        match self.fill(usize::MAX) {
+            Ok(()) | Err(DecodeError::UnexpectedEof { .. }) => Ok(self.available()),
+            Err(e) => Err(e)
+        }
+    }
+}
@@ -1,4 +1,29 @@
-use std::convert::TryInto;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::iter::{FromIterator, FusedIterator};
+use core::marker::PhantomData;
+
+mod borrow;
+mod error;
+mod impls;
+#[cfg(feature = "std")]
+mod io;
+pub mod protobuf;
+mod slice;
+mod stream;
+
+pub use borrow::*;
+pub use error::*;
+#[cfg(feature = "std")]
+pub use io::*;
+pub use slice::*;
+pub use stream::*;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Endian {
@@ -6,191 +31,535 @@ pub enum Endian {
     LittleEndian
 }
 
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Encoding {
+    Fixed,
+    Varint,
+    Protobuf
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum LenWidth {
+    U16,
+    U32,
+    U64
+}
+
+impl LenWidth {
+    pub fn max(self) -> u64 {
+        match self {
+            LenWidth::U16 => u16::MAX as u64,
+            LenWidth::U32 => u32::MAX as u64,
+            LenWidth::U64 => u64::MAX
+        }
+    }
+}
+
+// Overwrite replaces the bytes at the write position, Insert moves them after the new ones,
+// AppendOnly fails unless the write position is at the end.
+// Insert moves the bytes after the write position on every primitive write, so a value with k
+// fields costs k times the length of that tail. Buffer::write_value moves it once per value
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum WriteMode {
+    Overwrite,
+    Insert,
+    AppendOnly
+}
+
+// Separate reads at `pos` and appends at the write position, Shared reads and overwrites at `pos`
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Cursor {
+    Separate,
+    Shared
+}
+
 #[derive(Debug)]
 pub struct Buffer {
     data: Vec<u8>,
     pub pos: usize,
+    write_pos: usize,
+    pub cursor: Cursor,
+    pub write_mode: WriteMode,
     pub endian: Endian,
+    pub encoding: Encoding,
+    pub len_width: LenWidth,
 }
 
 pub trait ProtoWriter {
-    fn proto_write(&self, buf: &mut Buffer);
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError>;
+
+    fn proto_write<S: ProtoSink>(&self, buf: &mut S) {
+        if let Err(e) = self.try_proto_write(buf) {
+            panic!("{}", e)
+        }
+    }
+
+    // lets u8 and fixed size numbers write a whole slice at once, used by [T] and Vec<T>
+    #[doc(hidden)]
+    fn try_proto_write_slice<S: ProtoSink>(items: &[Self], buf: &mut S) -> Result<(), EncodeError> where Self: Sized {
+        for el in items.iter() {
+            el.try_proto_write(buf)?;
+        }
+
+        Ok(())
+    }
 }
 
-pub trait ProtoReader {
-    fn proto_read(buf: &mut Buffer) -> Self;
+pub trait ProtoReader: Sized {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError>;
+
+    #[doc(hidden)]
+    fn try_proto_read_vec<S: ProtoSource>(len: usize, buf: &mut S) -> Result<Vec<Self>, DecodeError> {
+        // a broken length must not make us allocate more than the source could ever hold
+        let mut v = Vec::with_capacity(len.min(buf.available()));
+
+        for _i in 0..len {
+            v.push(Self::try_proto_read(buf)?)
+        }
+
+        Ok(v)
+    }
+
+    fn proto_read<S: ProtoSource>(buf: &mut S) -> Self {
+        match Self::try_proto_read(buf) {
+            Ok(v) => v,
+            Err(e) => panic!("{}", e)
+        }
+    }
 }
 
 impl ProtoWriter for u8 {
-    fn proto_write(&self, buf: &mut Buffer) {
-       buf.write_u8(self);
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+       buf.try_write_u8(*self)
+    }
+
+    fn try_proto_write_slice<S: ProtoSink>(items: &[Self], buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_bytes(items)
     }
 } 
 
 impl ProtoWriter for i8 {
-    fn proto_write(&self, buf: &mut Buffer) {
-       let u = *self as u8;
-       buf.write_u8(&u);
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+       buf.try_write_u8(*self as u8)
     }
 } 
 
 impl ProtoWriter for bool {
-    fn proto_write(&self, buf: &mut Buffer) {
-       let u = if *self {1} else {0};
-       buf.write_u8(&u);
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+       buf.try_write_u8(if *self {1} else {0})
     }
 } 
 
 impl ProtoWriter for () {
-    fn proto_write(&self, buf: &mut Buffer) {
-       buf.write_u8(&1);
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+       buf.try_write_u8(1)
     }
 } 
 
-impl ProtoWriter for &str {
-    fn proto_write(&self, buf: &mut Buffer) {
-        buf.write_utf8(self);
+impl<T:ProtoWriter + ?Sized> ProtoWriter for &T {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        (**self).try_proto_write(buf)
     }
-} 
+}
+
+impl ProtoWriter for str {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_utf8(self)
+    }
+}
 
 impl ProtoWriter for String {
-    fn proto_write(&self, buf: &mut Buffer) {
-        buf.write_utf8(self);
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_utf8(self)
     }
 }
 
 impl<T:ProtoWriter> ProtoWriter for Option<T>  {
-    fn proto_write(&self, buf: &mut Buffer) {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
         match &self {
             Some(v) => {
-                1u8.proto_write(buf);
-                v.proto_write(buf);
+                1u8.try_proto_write(buf)?;
+                v.try_proto_write(buf)
             },
-            None => 0u8.proto_write(buf)
+            None => 0u8.try_proto_write(buf)
         }
     }
 }
 
-impl<T:ProtoWriter> ProtoWriter for Vec<T>  {
-    fn proto_write(&self, buf: &mut Buffer) {
-        self.len().proto_write(buf);
+impl<T:ProtoWriter> ProtoWriter for [T]  {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_len(self.len())?;
+        T::try_proto_write_slice(self, buf)
+    }
+}
 
-        for el in self.iter() {
-            el.proto_write(buf);
-        }
+impl<T:ProtoWriter> ProtoWriter for Vec<T>  {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        self.as_slice().try_proto_write(buf)
     }
 }   
 
+impl<T:ProtoWriter + ?Sized> ProtoWriter for Box<T>  {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        (**self).try_proto_write(buf)
+    }
+}
+
+// converts the slice a chunk at a time on the stack, so the sink gets few large writes.
+// When the endian matches the host the memory already holds the encoding and is written in one go
+macro_rules! write_fixed_slice {
+    ($t:ty, $items:expr, $buf:expr) => {{
+        const SIZE: usize = core::mem::size_of::<$t>();
+        let big_endian = $buf.endian() == Endian::BigEndian;
+
+        if big_endian == cfg!(target_endian = "big") {
+            // the numeric types have no padding, so every byte of the slice is initialized
+            let bytes = unsafe { core::slice::from_raw_parts($items.as_ptr() as *const u8, core::mem::size_of_val($items)) };
+
+            return $buf.try_write_bytes(bytes);
+        }
+
+        let mut out = [0u8; 1024];
+
+        for items in $items.chunks(out.len() / SIZE) {
+            for (chunk, v) in out.chunks_exact_mut(SIZE).zip(items.iter()) {
+                chunk.copy_from_slice(&if big_endian { v.to_be_bytes() } else { v.to_le_bytes() });
+            }
+
+            $buf.try_write_bytes(&out[..items.len() * SIZE])?;
+        }
+
+        Ok(())
+    }}
+}
+
 macro_rules! impl_ProtoWrite {
     ($($t:ty), +) => {
         $(impl ProtoWriter for $t {
-            fn proto_write(&self, buf:&mut Buffer) {
-                if buf.endian == Endian::BigEndian {
-                    buf.write_slice_u8(&self.to_be_bytes()) 
+            fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+                if buf.endian() == Endian::BigEndian {
+                    buf.try_write_bytes(&self.to_be_bytes()) 
                 } else {
-                    buf.write_slice_u8(&self.to_le_bytes()) 
+                    buf.try_write_bytes(&self.to_le_bytes()) 
                 }
             }
+
+            fn try_proto_write_slice<S: ProtoSink>(items: &[Self], buf: &mut S) -> Result<(), EncodeError> {
+                write_fixed_slice!($t, items, buf)
+            }
         })*
     }
 }
 
-impl ProtoReader for u8 {
-    fn proto_read(buf: &mut Buffer) -> Self {
-        buf.read_u8()
+macro_rules! impl_ProtoWrite_varint {
+    ($($t:ty), +) => {
+        $(impl ProtoWriter for $t {
+            fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+                if buf.encoding() != Encoding::Fixed {
+                    buf.try_write_varint(*self as u64)
+                } else if buf.endian() == Endian::BigEndian {
+                    buf.try_write_bytes(&self.to_be_bytes()) 
+                } else {
+                    buf.try_write_bytes(&self.to_le_bytes()) 
+                }
+            }
+
+            fn try_proto_write_slice<S: ProtoSink>(items: &[Self], buf: &mut S) -> Result<(), EncodeError> {
+                if buf.encoding() != Encoding::Fixed {
+                    for el in items.iter() {
+                        el.try_proto_write(buf)?;
+                    }
+
+                    Ok(())
+                } else {
+                    write_fixed_slice!($t, items, buf)
+                }
+            }
+        })*
     }
 }
 
-impl ProtoReader for i8 {
-    fn proto_read(buf: &mut Buffer) -> Self {
-        buf.read_u8() as i8
+macro_rules! impl_ProtoWrite_zigzag {
+    ($($t:ty), +) => {
+        $(impl ProtoWriter for $t {
+            fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+                if buf.encoding() != Encoding::Fixed {
+                    buf.try_write_varint(zigzag_encode(*self as i64))
+                } else if buf.endian() == Endian::BigEndian {
+                    buf.try_write_bytes(&self.to_be_bytes()) 
+                } else {
+                    buf.try_write_bytes(&self.to_le_bytes()) 
+                }
+            }
+
+            fn try_proto_write_slice<S: ProtoSink>(items: &[Self], buf: &mut S) -> Result<(), EncodeError> {
+                if buf.encoding() != Encoding::Fixed {
+                    for el in items.iter() {
+                        el.try_proto_write(buf)?;
+                    }
+
+                    Ok(())
+                } else {
+                    write_fixed_slice!($t, items, buf)
+                }
+            }
+        })*
     }
 }
 
-impl ProtoReader for bool {
-    fn proto_read(buf: &mut Buffer) -> Self {
-        if buf.read_u8() == 0 {false} else {true}
+// primitives decode the same way from any source, a borrowed BufferView included
+macro_rules! impl_readers {
+    ($t:ty, $buf:ident => $body:expr $(, $($owned:tt)+)?) => {
+        impl ProtoReader for $t {
+            fn try_proto_read<S: ProtoSource>($buf: &mut S) -> Result<Self, DecodeError> {
+                $body
+            }
+
+            $($($owned)+)?
+        }
+
+        impl<'a> ProtoBorrowRead<'a> for $t {
+            fn try_borrow_read(buf: &mut BufferView<'a>) -> Result<Self, DecodeError> {
+                Self::try_proto_read(buf)
+            }
+
+            fn try_borrow_read_vec(len: usize, buf: &mut BufferView<'a>) -> Result<Vec<Self>, DecodeError> {
+                Self::try_proto_read_vec(len, buf)
+            }
+        }
     }
 }
 
-impl ProtoReader for () {
-    fn proto_read(buf: &mut Buffer) -> Self {
-        let _ = buf.read_u8();
-        ()
+impl_readers! (u8, buf => buf.try_read_u8(),
+    fn try_proto_read_vec<S: ProtoSource>(len: usize, buf: &mut S) -> Result<Vec<Self>, DecodeError> {
+        Ok(buf.try_read_exact(len)?.to_vec())
     }
-}
+);
+impl_readers! (i8, buf => Ok(buf.try_read_u8()? as i8));
+impl_readers! (bool, buf => Ok(buf.try_read_u8()? != 0));
+impl_readers! ((), buf => {
+    buf.try_read_u8()?;
+    Ok(())
+});
 
 impl<T:ProtoReader> ProtoReader for Option<T> {
-    fn proto_read(buf: &mut Buffer) -> Self {
-        match buf.read_u8() {
-            0 => None,
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let offset = buf.pos();
+
+        match buf.try_read_u8()? {
+            0 => Ok(None),
             1 => {
-                let v = T::proto_read(buf);
-                Some(v)
+                let v = T::try_proto_read(buf)?;
+                Ok(Some(v))
             },
-            _ => panic!{"Option can not has value"}
+            tag => Err(DecodeError::InvalidTag { offset, tag })
         }
     }
 }
 
 impl<T:ProtoReader> ProtoReader for Vec<T> {
-    fn proto_read(buf: &mut Buffer) -> Self {
-        let len = usize::proto_read(buf);
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let len = buf.try_read_len()?;
+
+        T::try_proto_read_vec(len, buf)
+    }
+}
 
-        let mut v = Vec::with_capacity(len);
+impl<T:ProtoReader> ProtoReader for Box<T> {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        Ok(Box::new(T::try_proto_read(buf)?))
+    }
+}
+
+macro_rules! read_fixed {
+    ($t:ty, $buf:expr) => {{
+        let big_endian = $buf.endian() == Endian::BigEndian;
+        let bytes = $buf.try_read_exact(core::mem::size_of::<$t>())?.try_into().unwrap();
 
-        if len > 0 {
-            for _i in 0..len {
-                v.push(T::proto_read(buf))
+        if big_endian {
+            Ok(<$t>::from_be_bytes(bytes))
+        } else {
+            Ok(<$t>::from_le_bytes(bytes))
+        }
+    }}
+}
+
+// reads in chunks, so a broken length makes a streaming source fail at the end of its data
+// instead of buffering everything the length promises
+macro_rules! read_fixed_vec {
+    ($t:ty, $len:expr, $buf:expr) => {{
+        const SIZE: usize = core::mem::size_of::<$t>();
+        let big_endian = $buf.endian() == Endian::BigEndian;
+        let mut v = Vec::with_capacity($len.min($buf.available() / SIZE));
+        let mut left = $len;
+
+        while left > 0 {
+            let n = left.min((1 << 16) / SIZE);
+            let bytes = $buf.try_read_exact(n * SIZE)?;
+
+            if big_endian == cfg!(target_endian = "big") {
+                let start = v.len();
+                v.resize(start + n, 0 as $t);
+
+                // every bit pattern is a valid value of the numeric types
+                let dst = unsafe { core::slice::from_raw_parts_mut(v[start..].as_mut_ptr() as *mut u8, n * SIZE) };
+                dst.copy_from_slice(bytes);
+            } else if big_endian {
+                v.extend(bytes.chunks_exact(SIZE).map(|c| <$t>::from_be_bytes(c.try_into().unwrap())));
+            } else {
+                v.extend(bytes.chunks_exact(SIZE).map(|c| <$t>::from_le_bytes(c.try_into().unwrap())));
             }
+
+            left -= n;
         }
 
-        v
-    }
+        Ok(v)
+    }}
 }
 
 macro_rules! impl_ProtoReader {
     ($($t:ty), +) => {
-        $(impl ProtoReader for $t {
-            fn proto_read(buf:&mut Buffer) -> Self {
-                if buf.endian == Endian::BigEndian {            
-                    Self::from_be_bytes(buf.read_slice_u8(std::mem::size_of::<Self>()).try_into().unwrap())
+        $(impl_readers! ($t, buf => read_fixed!($t, buf),
+            fn try_proto_read_vec<S: ProtoSource>(len: usize, buf: &mut S) -> Result<Vec<Self>, DecodeError> {
+                read_fixed_vec!($t, len, buf)
+            }
+        );)*
+    }
+}
+
+macro_rules! impl_ProtoReader_varint {
+    ($($t:ty), +) => {
+        $(impl_readers! ($t, buf => {
+            if buf.encoding() != Encoding::Fixed {
+                let offset = buf.pos();
+                let v = buf.try_read_varint()?;
+
+                v.try_into().map_err(|_| DecodeError::InvalidVarint { offset })
+            } else {
+                read_fixed!($t, buf)
+            }
+        },
+            fn try_proto_read_vec<S: ProtoSource>(len: usize, buf: &mut S) -> Result<Vec<Self>, DecodeError> {
+                if buf.encoding() != Encoding::Fixed {
+                    let mut v = Vec::with_capacity(len.min(buf.available()));
+
+                    for _i in 0..len {
+                        v.push(Self::try_proto_read(buf)?)
+                    }
+
+                    Ok(v)
                 } else {
-                    Self::from_le_bytes(buf.read_slice_u8(std::mem::size_of::<Self>()).try_into().unwrap())
+                    read_fixed_vec!($t, len, buf)
                 }
             }
-        })*
+        );)*
     }
 }
 
-impl_ProtoWrite! (u16, u32, u64, usize, f32, f64, i16, i32, i64, &u16, &u32, &u64, &usize, &f32, &f64, &i16, &i32, &i64);
-impl_ProtoReader! (u16, u32, u64, usize, f32, f64, i16, i32, i64);
+macro_rules! impl_ProtoReader_zigzag {
+    ($($t:ty), +) => {
+        $(impl_readers! ($t, buf => {
+            if buf.encoding() != Encoding::Fixed {
+                let offset = buf.pos();
+                let v = zigzag_decode(buf.try_read_varint()?);
+
+                v.try_into().map_err(|_| DecodeError::InvalidVarint { offset })
+            } else {
+                read_fixed!($t, buf)
+            }
+        },
+            fn try_proto_read_vec<S: ProtoSource>(len: usize, buf: &mut S) -> Result<Vec<Self>, DecodeError> {
+                if buf.encoding() != Encoding::Fixed {
+                    let mut v = Vec::with_capacity(len.min(buf.available()));
+
+                    for _i in 0..len {
+                        v.push(Self::try_proto_read(buf)?)
+                    }
 
-impl ProtoReader for String {
-    fn proto_read(buf: &mut Buffer) -> Self {
-        String::from(buf.read_utf8())
+                    Ok(v)
+                } else {
+                    read_fixed_vec!($t, len, buf)
+                }
+            }
+        );)*
     }
 }
 
-fn char_from_u32(i:u32) -> char {
-    pub const MAX: char = '\u{10ffff}';
+// shared by Buffer and BufferView
+fn check_remaining(data: &[u8], pos: usize, len: usize) -> Result<(), DecodeError> {
+    let remaining = data.len().saturating_sub(pos);
 
-    if (i > MAX as u32) || (i >= 0xD800 && i <= 0xDFFF) {
-        panic!("char_from_u32 ({})", i)
+    if remaining < len {
+        Err(DecodeError::UnexpectedEof { offset: pos, needed: len - remaining })
     } else {
-        unsafe { std::mem::transmute(i) }
+        Ok(())
     }
 }
 
+fn read_slice<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], DecodeError> {
+    check_remaining(data, *pos, len)?;
+    *pos += len;
+
+    Ok(&data[(*pos - len) .. *pos])
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<u64, DecodeError> {
+    let offset = *pos;
+
+    decode_varint(offset, || Ok(read_slice(data, pos, 1)?[0]))
+}
+
+// shared by every source, `next` hands out one byte at a time
+fn decode_varint(offset: usize, mut next: impl FnMut() -> Result<u8, DecodeError>) -> Result<u64, DecodeError> {
+    let mut v = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let byte = next()?;
+
+        // the tenth byte may only carry the highest bit of a u64
+        if shift == 63 && byte > 1 {
+            return Err(DecodeError::InvalidVarint { offset });
+        }
+
+        v |= ((byte & 0x7f) as u64) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+
+    Err(DecodeError::InvalidVarint { offset })
+}
+
+fn zigzag_encode(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn zigzag_decode(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+impl_ProtoWrite! (f32, f64, u128, i128);
+impl_ProtoWrite_varint! (u16, u32, u64, usize);
+impl_ProtoWrite_zigzag! (i16, i32, i64, isize);
+impl_ProtoReader! (f32, f64, u128, i128);
+impl_ProtoReader_varint! (u16, u32, u64, usize);
+impl_ProtoReader_zigzag! (i16, i32, i64, isize);
+
+impl_readers! (String, buf => Ok(String::from(buf.try_read_utf8()?)));
+
 impl ProtoReader for char {
-    fn proto_read(buf: &mut Buffer) -> Self {
-        char_from_u32(u32::proto_read(buf))
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let offset = buf.pos();
+        let value = u32::try_proto_read(buf)?;
+
+        core::char::from_u32(value).ok_or(DecodeError::InvalidChar { offset, value })
     }
 }
 
 impl ProtoWriter for char {
-    fn proto_write(&self, buf: &mut Buffer) {
-        (*self as u32).proto_write(buf)
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        (*self as u32).try_proto_write(buf)
     }
 }
 
@@ -198,135 +567,268 @@ impl Buffer {
 
     pub fn build_buffer(cap: usize, endian: Endian) -> Buffer{
         Buffer {
-            data: Vec::with_capacity(cap),
-            pos: 0,
-            endian        
+            endian,
+            ..Buffer::from_vec(Vec::with_capacity(cap))
         }
     }
 
     pub fn new() -> Buffer {
+        Buffer::from_vec(Vec::new())
+    }
+
+    pub fn from_vec(data: Vec<u8>) -> Buffer {
         Buffer {
-            data: Vec::new(),
+            write_pos: data.len(),
+            data,
             pos: 0,
-            endian: Endian::BigEndian
+            cursor: Cursor::Separate,
+            write_mode: WriteMode::Overwrite,
+            endian: Endian::BigEndian,
+            encoding: Encoding::Fixed,
+            len_width: LenWidth::U64
         }
     }
 
-    fn write_u8(&mut self, v:&u8) {        
-        if self.pos == self.data.len() {
-            self.data.push(*v);
-        } else {
-            self.data[self.pos] = *v;
-        }
+    pub fn from_slice(data: &[u8]) -> Buffer {
+        Buffer::from_vec(data.to_vec())
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
 
-        self.pos += 1; 
+    pub fn len(&self) -> usize {
+        self.data.len()
     }
 
-    fn read_u8(&mut self) -> u8 {
-        self.pos += 1;
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.pos = 0;
+        self.write_pos = 0;
+    }
 
-        self.data[self.pos - 1]
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+        self.pos = self.pos.min(self.data.len());
+        self.write_pos = self.write_pos.min(self.data.len());
     }
 
-    fn write_slice_u8(&mut self, v:&[u8]) {        
-        let mut data_len = self.data.len();
+    // drops the bytes that were already read
+    pub fn compact(&mut self) {
+        let consumed = self.pos.min(self.data.len());
 
-        if (data_len - self.pos) == 0 {
-            self.data.reserve(data_len + v.len());
+        self.data.drain(..consumed);
+        self.pos = 0;
+        self.write_pos = self.write_pos.saturating_sub(consumed);
+    }
+
+    pub fn seek(&mut self, pos: usize) -> Result<(), DecodeError> {
+        if pos > self.data.len() {
+            return Err(DecodeError::UnexpectedEof { offset: self.data.len(), needed: pos - self.data.len() });
         }
 
-        for i in v {
-            if self.pos == data_len {
-                self.data.push(*i);
-                data_len += 1;
-            } else {
-                self.data[self.pos] = *i;
-            }
+        self.pos = pos;
+
+        Ok(())
+    }
 
-            self.pos += 1; 
+    pub fn write_pos(&self) -> usize {
+        match self.cursor {
+            Cursor::Separate => self.write_pos,
+            Cursor::Shared => self.pos
         }
     }
 
-    fn read_slice_u8(&mut self, len: usize) -> &[u8] {
-        self.pos += len;
+    pub fn seek_write(&mut self, pos: usize) -> Result<(), EncodeError> {
+        if pos > self.data.len() {
+            return Err(EncodeError::OutOfBounds { offset: pos, len: self.data.len() });
+        }
+
+        *self.write_cursor() = pos;
 
-        &self.data[(self.pos - len) .. self.pos]
+        Ok(())
     }
 
+    fn write_cursor(&mut self) -> &mut usize {
+        match self.cursor {
+            Cursor::Separate => &mut self.write_pos,
+            Cursor::Shared => &mut self.pos
+        }
+    }
 
-    pub fn write_utf8(&mut self, v:&str) {
-        let str_len = v.len();
+    // makes room for `len` bytes at the write position as the write mode says, and moves past them
+    fn write_region(&mut self, len: usize) -> Result<&mut [u8], EncodeError> {
+        let start = self.write_pos();
+        let end = start + len;
 
-        if self.data.capacity() < self.pos + str_len + std::mem::size_of::<usize>() {
-            self.data.reserve(self.pos + str_len + std::mem::size_of::<usize>() - self.data.len());//?
+        if start > self.data.len() {
+            return Err(EncodeError::OutOfBounds { offset: start, len: self.data.len() });
         }
 
-        str_len.proto_write(self);
+        match self.write_mode {
+            WriteMode::Overwrite => {
+                if self.data.len() < end {
+                    self.data.resize(end, 0);
+                }
+            },
+            WriteMode::Insert => {
+                self.data.resize(self.data.len() + len, 0);
+                self.data[start..].rotate_right(len);
+
+                // unread bytes after the insertion point move along
+                if self.cursor == Cursor::Separate && self.pos > start {
+                    self.pos += len;
+                }
+            },
+            WriteMode::AppendOnly => {
+                if start != self.data.len() {
+                    return Err(EncodeError::NotAppending { offset: start, len: self.data.len() });
+                }
 
-        let mut add_len = 0;
-        if self.data.len() < self.pos + str_len {
-            add_len = self.pos + str_len - self.data.len();
-            add_len = if add_len > 0 {add_len} else {0};
+                self.data.resize(end, 0);
+            }
         }
 
-        unsafe {
-            v.as_ptr().copy_to(self.data.as_mut_ptr().offset(self.pos as isize), v.len());
+        *self.write_cursor() = end;
 
-            if add_len > 0 {
-                self.data.set_len(self.pos + add_len);
-            }
+        Ok(&mut self.data[start..end])
+    }
+
+    fn write_slice_u8(&mut self, v:&[u8]) -> Result<(), EncodeError> {        
+        self.write_region(v.len())?.copy_from_slice(v);
+
+        Ok(())
+    }
 
-            self.pos += str_len; 
+    // encodes `v` on its own first and then copies it to the write position in one go
+    pub fn try_write_value<T: ProtoWriter + ?Sized>(&mut self, v: &T) -> Result<(), EncodeError> {
+        let offset = self.write_pos();
+
+        let mut staged = Buffer::new();
+        staged.endian = self.endian;
+        staged.encoding = self.encoding;
+        staged.len_width = self.len_width;
+
+        v.try_proto_write(&mut staged).map_err(|e| e.offset_by(offset))?;
+        self.write_slice_u8(staged.as_slice())
+    }
+
+    pub fn write_value<T: ProtoWriter + ?Sized>(&mut self, v: &T) {
+        if let Err(e) = self.try_write_value(v) {
+            panic!("{}", e)
+        }
+    }
+
+    pub fn write_len(&mut self, len: usize) {
+        if let Err(e) = self.try_write_len(len) {
+            panic!("{}", e)
         }
     }
 
+    pub fn write_utf8(&mut self, v:&str) {
+        if let Err(e) = self.try_write_utf8(v) {
+            panic!("{}", e)
+        }
+    }
 
     pub fn read_utf8(&mut self) -> &str {
-        let len = usize::proto_read(self);
-
-        std::str::from_utf8(self.read_slice_u8(len)).unwrap()
+        match self.try_read_utf8() {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e)
+        }
     }
 
     pub fn write_iter<T:ProtoWriter>(&mut self, it:&mut dyn Iterator<Item = T>) {
+        if let Err(e) = self.try_write_iter(it) {
+            panic!("{}", e)
+        }
+    }
+
+    pub fn try_write_iter<T:ProtoWriter>(&mut self, it:&mut dyn Iterator<Item = T>) -> Result<(), EncodeError> {
+        let prefix = self.begin_len_prefix()?;
         let mut size:usize = 0;
-        size.proto_write(self);
 
-        while let Some(v) = it.next() {
+        for v in it {
             size += 1;
-            v.proto_write(self);
+            v.try_proto_write(self)?;
         }
 
-        let old_pos = self.pos;
+        self.patch_len(prefix, size)
+    }
 
-        self.pos = 0;
-        size.proto_write(self);        
-        self.pos = old_pos;
+    // reads the count written by write_iter, the elements are decoded one at a time
+    pub fn read_iter<T:ProtoReader>(&mut self) -> ReadIter<'_, T> {
+        match self.try_read_iter() {
+            Ok(it) => it,
+            Err(e) => panic!("{}", e)
+        }
     }
 
-    /*pub fn read_iter<T:ProtoReader>(&mut self) -> impl Iterator<Item = T> {
+    pub fn try_read_iter<T:ProtoReader>(&mut self) -> Result<ReadIter<'_, T>, DecodeError> {
+        let remaining = self.try_read_len()?;
 
-    }*/
+        Ok(ReadIter { buf: self, remaining, _marker: PhantomData })
+    }
 }
 
-use std::iter::FromIterator;
+// an error ends the iteration, the elements after it are not read
+pub struct ReadIter<'b, T> {
+    buf: &'b mut Buffer,
+    remaining: usize,
+    _marker: PhantomData<fn() -> T>,
+}
 
-impl<T:ProtoWriter> FromIterator<T> for Buffer {
-    fn from_iter<I: IntoIterator<Item=T>>(iter: I) -> Self {
-        let mut buf = Buffer::new();
+impl<'b, T:ProtoReader> Iterator for ReadIter<'b, T> {
+    type Item = Result<T, DecodeError>;
 
-        let mut size:usize = 0;
-        size.proto_write(&mut buf);
-       
-        for i in iter {
-            size += 1;
-            i.proto_write(&mut buf);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
         }
 
-        let old_pos = buf.pos;
+        match T::try_proto_read(self.buf) {
+            Ok(v) => {
+                self.remaining -= 1;
+                Some(Ok(v))
+            },
+            Err(e) => {
+                self.remaining = 0;
+                Some(Err(e))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'b, T:ProtoReader> ExactSizeIterator for ReadIter<'b, T> {}
+
+impl<'b, T:ProtoReader> FusedIterator for ReadIter<'b, T> {}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Buffer::new()
+    }
+}
 
-        buf.pos = 0;
-        size.proto_write(&mut buf);        
-        buf.pos = old_pos;
+impl<T:ProtoWriter> FromIterator<T> for Buffer {
+    fn from_iter<I: IntoIterator<Item=T>>(iter: I) -> Self {
+        let mut buf = Buffer::new();
+        buf.write_iter(&mut iter.into_iter());
 
         buf
     }
@@ -337,6 +839,8 @@ impl<T:ProtoWriter> FromIterator<T> for Buffer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloc::vec;
+    #[cfg(feature = "std")]
     use std::time::Instant;
 
     #[derive(Debug, PartialEq)]
@@ -347,20 +851,20 @@ mod tests {
     }
 
     impl ProtoWriter for User {
-        fn proto_write(&self, buf:&mut Buffer) {
-            self.name.proto_write(buf);
-            self.email.proto_write(buf);
-            self.age.proto_write(buf);
+        fn try_proto_write<S: ProtoSink>(&self, buf:&mut S) -> Result<(), EncodeError> {
+            self.name.try_proto_write(buf)?;
+            self.email.try_proto_write(buf)?;
+            self.age.try_proto_write(buf)
         }
     }
 
     impl ProtoReader for User {
-        fn proto_read(buf:&mut Buffer) -> Self {
-            return User {
-                name: String::proto_read(buf),
-                email: String::proto_read(buf),
-                age: u8::proto_read(buf)
-            }
+        fn try_proto_read<S: ProtoSource>(buf:&mut S) -> Result<Self, DecodeError> {
+            Ok(User {
+                name: String::try_proto_read(buf)?,
+                email: String::try_proto_read(buf)?,
+                age: u8::try_proto_read(buf)?
+            })
         }
     }
 
@@ -375,13 +879,13 @@ mod tests {
 
         user.proto_write(&mut b);
 
-        b.pos = 0;
         let readed_user = User::proto_read(&mut b);
         
         assert_eq!(user, readed_user);
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn measure() {
         let d1 = || {
             let mut b = Buffer::new();
@@ -413,9 +917,120 @@ mod tests {
         let v = vec![1u16, 2, 3, 4];
 
         let mut b = Buffer::from_iter(v.iter());
-        b.pos = 0;
 
         assert_eq!(v, Vec::proto_read(&mut b));
+
+        let users = vec![
+            User { name: String::from("Den"), email: String::from("den@mail.com"), age: 37 },
+            User { name: String::from("Ann"), email: String::from("ann@mail.com"), age: 30 }
+        ];
+
+        let mut b = Buffer::from_iter(users.iter());
+
+        assert_eq!(users, Vec::proto_read(&mut b));
+
+        let names = vec![String::from("a"), String::from("b")];
+
+        let mut b = Buffer::from_iter(names.iter());
+
+        assert_eq!(names, Vec::<String>::proto_read(&mut b));
+    }
+
+    #[test]
+    fn read_iter() {
+        let users = [
+            User { name: String::from("Den"), email: String::from("den@mail.com"), age: 37 },
+            User { name: String::from("Ann"), email: String::from("ann@mail.com"), age: 30 }
+        ];
+
+        let mut b = Buffer::new();
+        b.write_iter(&mut users.iter());
+
+        let mut it = b.read_iter::<User>();
+        assert_eq!((2, Some(2)), it.size_hint());
+        assert_eq!(users[0], it.next().unwrap().unwrap());
+        assert_eq!(1, it.len());
+        assert_eq!(users[1], it.next().unwrap().unwrap());
+        assert!(it.next().is_none());
+        assert_eq!(0, b.remaining());
+
+        // the second element is cut off
+        let mut b = Buffer::from_vec(vec![0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0]);
+        let items: Vec<_> = b.read_iter::<u16>().collect();
+        assert_eq!(vec![Ok(1), Err(DecodeError::UnexpectedEof { offset: 10, needed: 1 })], items);
+
+        let mut b = Buffer::from_vec(vec![0, 0, 1]);
+        assert_eq!(Some(DecodeError::UnexpectedEof { offset: 0, needed: 5 }), b.try_read_iter::<u8>().err());
+    }
+
+    #[test]
+    fn bulk_slices() {
+        let bytes: Vec<u8> = (0..1 << 20).map(|i| i as u8).collect();
+        let floats: Vec<f32> = (0..1000).map(|i| i as f32 / 3.0).collect();
+        let ints: Vec<i64> = (-500..500).collect();
+
+        for endian in [Endian::BigEndian, Endian::LittleEndian].iter() {
+            for encoding in [Encoding::Fixed, Encoding::Varint].iter() {
+                let mut b = Buffer::build_buffer(0, *endian);
+                b.encoding = *encoding;
+
+                bytes.proto_write(&mut b);
+                floats.proto_write(&mut b);
+                ints.proto_write(&mut b);
+
+                // the same bytes as writing element by element
+                let mut expected = Buffer::build_buffer(0, *endian);
+                expected.encoding = *encoding;
+
+                expected.write_len(bytes.len());
+                bytes.iter().for_each(|v| v.proto_write(&mut expected));
+                expected.write_len(floats.len());
+                floats.iter().for_each(|v| v.proto_write(&mut expected));
+                expected.write_len(ints.len());
+                ints.iter().for_each(|v| v.proto_write(&mut expected));
+
+                assert_eq!(expected.as_slice(), b.as_slice());
+
+                assert_eq!(bytes, Vec::<u8>::proto_read(&mut b));
+                assert_eq!(floats, Vec::<f32>::proto_read(&mut b));
+                assert_eq!(ints, Vec::<i64>::proto_read(&mut b));
+            }
+        }
+
+        // writing in the middle overwrites and then grows the data
+        let mut b = Buffer::from_vec(vec![9, 9, 9, 9]);
+        b.cursor = Cursor::Shared;
+        b.pos = 2;
+        let items: &[u16] = &[1, 2];
+        items.proto_write(&mut b);
+        assert_eq!(&[9, 9, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0, 2], b.as_slice());
+
+        let mut b = Buffer::from_vec(vec![0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 1, 0]);
+        assert_eq!(
+            Err(DecodeError::UnexpectedEof { offset: 8, needed: 3 }),
+            Vec::<u32>::try_proto_read(&mut b)
+        );
+    }
+
+    #[test]
+    fn references() {
+        let mut b = Buffer::new();
+
+        let v = [1u8, 2, 3];
+        let tail: &[u8] = &v[1..];
+        let s = String::from("Den");
+
+        v[..2].proto_write(&mut b);
+        tail.proto_write(&mut b);
+        (&&s).proto_write(&mut b);
+        vec![&s, &s].proto_write(&mut b);
+        Box::<str>::from("box").proto_write(&mut b);
+
+        assert_eq!(vec![1, 2], Vec::<u8>::proto_read(&mut b));
+        assert_eq!(vec![2, 3], Vec::<u8>::proto_read(&mut b));
+        assert_eq!(s, String::proto_read(&mut b));
+        assert_eq!(vec![s.clone(), s], Vec::<String>::proto_read(&mut b));
+        assert_eq!("box", String::proto_read(&mut b));
     }
 
     #[test]
@@ -425,8 +1040,6 @@ mod tests {
         let v = vec![1, 2, 4, 6];
         v.proto_write(&mut b);
 
-        b.pos = 0;
-        
         assert_eq!(v, Vec::proto_read(&mut b));
     }
 
@@ -438,8 +1051,6 @@ mod tests {
         v.proto_write(&mut b);
         0u8.proto_write(&mut b);
 
-        b.pos = 0;
-        
         let v_none:Option<u8> = None;
 
         assert_eq!(v, Option::proto_read(&mut b));
@@ -451,11 +1062,12 @@ mod tests {
             #[test]
             fn $func_name() {
                 let mut b = Buffer::new();
+                b.cursor = Cursor::Shared;
                 $v0.proto_write(&mut b);
                 $v1.proto_write(&mut b);
                 $v2.proto_write(&mut b);
 
-                b.pos = std::mem::size_of::<$t>();
+                b.pos = core::mem::size_of::<$t>();
 
                 assert_eq!($v1, <$t>::proto_read(&mut b));
 
@@ -490,13 +1102,15 @@ mod tests {
     #[test]
     fn str() {
         let mut b = Buffer::new();
+        b.cursor = Cursor::Shared;
 
         b.write_utf8("[DIY家具] 収納椅子をつくる");
         b.pos = 0;
 
         assert_eq!("[DIY家具] 収納椅子をつくる", b.read_utf8());
 
-        b.pos = 11 + std::mem::size_of::<usize>();
+        // past the 8 byte length prefix
+        b.pos = 8 + 11;
         0x5fu8.proto_write(&mut b);
 
         b.pos = 0;
@@ -507,6 +1121,7 @@ mod tests {
     #[test]
     fn proto_string() {
         let mut b = Buffer::new();
+        b.cursor = Cursor::Shared;
 
         3u8.proto_write(&mut b);
         "[DIY家具] 収納椅子をつくる".proto_write(&mut b);
@@ -514,7 +1129,7 @@ mod tests {
 
         assert_eq!("[DIY家具] 収納椅子をつくる", String::proto_read(&mut b));
 
-        b.pos = 1 + 11 + std::mem::size_of::<usize>();
+        b.pos = 1 + 8 + 11;
         0x5fu8.proto_write(&mut b);
 
         b.pos = 1;
@@ -531,6 +1146,8 @@ mod tests {
         let c2 = "納".chars().next().unwrap();
         let c3 = "۩".chars().next().unwrap();
 
+        b.cursor = Cursor::Shared;
+
         c0.proto_write(&mut b);
         c1.proto_write(&mut b);
         c2.proto_write(&mut b);
@@ -548,5 +1165,599 @@ mod tests {
 
         assert_eq!(c3, char::proto_read(&mut b));
     }
+
+    #[test]
+    fn bytes() {
+        let mut b = Buffer::new();
+
+        let user = User {
+            name: String::from("Den"),
+            email: String::from("nastvood@gmail.com"),
+            age: 37
+        };
+
+        user.proto_write(&mut b);
+        assert_eq!(b.len(), b.as_slice().len());
+
+        let mut received = Buffer::from_slice(&b.into_vec());
+        assert_eq!(user, User::proto_read(&mut received));
+        assert_eq!(0, received.remaining());
+
+        received.seek(received.len() - 1).unwrap();
+        assert_eq!(1, received.remaining());
+        assert!(received.seek(received.len() + 1).is_err());
+
+        received.truncate(3);
+        assert_eq!(3, received.len());
+        assert_eq!(3, received.pos);
+
+        received.clear();
+        assert!(received.is_empty());
+        assert_eq!(0, received.pos);
+
+        let mut b = Buffer::from_vec(vec![0, 1, 2]);
+        assert_eq!(1u16, u16::proto_read(&mut b));
+        assert_eq!(vec![0, 1, 2], b.into_vec());
+    }
+
+    #[test]
+    fn varint() {
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Varint;
+
+        300u32.proto_write(&mut b);
+        (-1i32).proto_write(&mut b);
+        "abc".proto_write(&mut b);
+
+        assert_eq!(&[0xac, 0x02, 0x01, 0x03, b'a', b'b', b'c'], b.as_slice());
+
+        u64::MAX.proto_write(&mut b);
+        i64::MIN.proto_write(&mut b);
+        i16::MAX.proto_write(&mut b);
+        vec![1usize, 128, 16384].proto_write(&mut b);
+        1.5f32.proto_write(&mut b);
+
+        assert_eq!(300, u32::proto_read(&mut b));
+        assert_eq!(-1, i32::proto_read(&mut b));
+        assert_eq!("abc", String::proto_read(&mut b));
+        assert_eq!(u64::MAX, u64::proto_read(&mut b));
+        assert_eq!(i64::MIN, i64::proto_read(&mut b));
+        assert_eq!(i16::MAX, i16::proto_read(&mut b));
+        assert_eq!(vec![1usize, 128, 16384], Vec::proto_read(&mut b));
+        assert_eq!(1.5f32, f32::proto_read(&mut b));
+        assert_eq!(0, b.remaining());
+    }
+
+    #[test]
+    fn varint_errors() {
+        let mut b = Buffer::from_vec(vec![0xff, 0xff, 0x04]);
+        b.encoding = Encoding::Varint;
+
+        assert_eq!(Err(DecodeError::InvalidVarint { offset: 0 }), u16::try_proto_read(&mut b));
+
+        let mut b = Buffer::from_vec(vec![0xff; 11]);
+        b.encoding = Encoding::Varint;
+
+        assert_eq!(Err(DecodeError::InvalidVarint { offset: 0 }), u64::try_proto_read(&mut b));
+
+        let mut b = Buffer::from_vec(vec![0x80, 0x80]);
+        b.encoding = Encoding::Varint;
+
+        assert_eq!(Err(DecodeError::UnexpectedEof { offset: 2, needed: 1 }), u32::try_proto_read(&mut b));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn std_types() {
+        use std::borrow::Cow;
+        use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+        use std::marker::PhantomData;
+        use std::num::{NonZeroI64, NonZeroU8, Wrapping};
+        use std::rc::Rc;
+        use std::sync::Arc;
+
+        let mut b = Buffer::new();
+
+        let map: HashMap<String, u32> = vec![(String::from("a"), 1), (String::from("b"), 2)].into_iter().collect();
+        let tree: BTreeMap<u8, Vec<i16>> = vec![(1, vec![-1]), (2, vec![])].into_iter().collect();
+        let set: HashSet<char> = "abc".chars().collect();
+        let tree_set: BTreeSet<u64> = vec![3, 1, 2].into_iter().collect();
+        let deque: VecDeque<bool> = vec![true, false].into_iter().collect();
+        let tuple = (1u8, String::from("Den"), -2i32, 'x', 1.5f64, (), true, 7u16, 8u32, 9u64, 10i8, [1u8, 2]);
+        let result: Vec<Result<u8, String>> = vec![Ok(1), Err(String::from("fail"))];
+        let cow: Cow<str> = Cow::Borrowed("cow");
+
+        map.proto_write(&mut b);
+        tree.proto_write(&mut b);
+        set.proto_write(&mut b);
+        tree_set.proto_write(&mut b);
+        deque.proto_write(&mut b);
+        [[1u16, 2], [3, 4]].proto_write(&mut b);
+        tuple.proto_write(&mut b);
+        Rc::new(5u8).proto_write(&mut b);
+        Arc::new(String::from("arc")).proto_write(&mut b);
+        cow.proto_write(&mut b);
+        result.proto_write(&mut b);
+        u128::MAX.proto_write(&mut b);
+        (-5i128).proto_write(&mut b);
+        NonZeroU8::new(3).unwrap().proto_write(&mut b);
+        NonZeroI64::new(-3).unwrap().proto_write(&mut b);
+        Wrapping(250u8).proto_write(&mut b);
+        PhantomData::<String>.proto_write(&mut b);
+
+        assert_eq!(map, HashMap::proto_read(&mut b));
+        assert_eq!(tree, BTreeMap::proto_read(&mut b));
+        assert_eq!(set, HashSet::proto_read(&mut b));
+        assert_eq!(tree_set, BTreeSet::proto_read(&mut b));
+        assert_eq!(deque, VecDeque::proto_read(&mut b));
+        assert_eq!([[1u16, 2], [3, 4]], <[[u16; 2]; 2]>::proto_read(&mut b));
+        assert_eq!(tuple, ProtoReader::proto_read(&mut b));
+        assert_eq!(Rc::new(5u8), Rc::proto_read(&mut b));
+        assert_eq!(Arc::new(String::from("arc")), Arc::proto_read(&mut b));
+        assert_eq!(cow, Cow::<str>::proto_read(&mut b));
+        assert_eq!(result, Vec::<Result<u8, String>>::proto_read(&mut b));
+        assert_eq!(u128::MAX, u128::proto_read(&mut b));
+        assert_eq!(-5, i128::proto_read(&mut b));
+        assert_eq!(NonZeroU8::new(3).unwrap(), NonZeroU8::proto_read(&mut b));
+        assert_eq!(NonZeroI64::new(-3).unwrap(), NonZeroI64::proto_read(&mut b));
+        assert_eq!(Wrapping(250u8), Wrapping::proto_read(&mut b));
+        assert_eq!(PhantomData, PhantomData::<String>::proto_read(&mut b));
+        assert_eq!(b.len(), b.pos);
+
+        let mut b = Buffer::from_vec(vec![0, 2]);
+        assert_eq!(Err(DecodeError::InvalidNonZero { offset: 0 }), NonZeroU8::try_proto_read(&mut b));
+        assert_eq!(Err(DecodeError::InvalidTag { offset: 1, tag: 2 }), Result::<u8, u8>::try_proto_read(&mut b));
+    }
+
+    #[test]
+    fn len_prefixed() {
+        let mut b = Buffer::new();
+        b.len_width = LenWidth::U16;
+
+        b.try_write_len_prefixed(|buf| {
+            1u16.try_proto_write(buf)?;
+            buf.try_write_len(0)
+        }).unwrap();
+        b.try_write_len_prefixed_bytes(&[7, 8]).unwrap();
+
+        assert_eq!(&[0, 4, 0, 1, 0, 0, 0, 2, 7, 8], b.as_slice());
+
+        assert_eq!(Ok(1), b.try_read_len_prefixed(|buf| {
+            let v = u16::try_proto_read(buf)?;
+            buf.try_read_len()?;
+            Ok(v)
+        }));
+        assert_eq!(Ok(vec![7, 8]), b.try_read_len_prefixed_bytes());
+
+        b.pos = 0;
+        assert_eq!(
+            Err(DecodeError::InvalidLength { offset: 0, len: 4 }),
+            b.try_read_len_prefixed(u16::try_proto_read)
+        );
+
+        b.pos = 0;
+        assert_eq!(Ok(()), b.try_skip_len_prefixed());
+        assert_eq!(6, b.pos);
+
+        let mut b = Buffer::from_vec(vec![0, 0, 0, 0, 0, 0, 0, 3, 1]);
+        assert_eq!(
+            Err(DecodeError::UnexpectedEof { offset: 8, needed: 2 }),
+            b.try_read_len_prefixed(u8::try_proto_read)
+        );
+    }
+
+    #[test]
+    fn len_width() {
+        let mut b = Buffer::new();
+        b.len_width = LenWidth::U32;
+
+        "abc".proto_write(&mut b);
+        vec![7u8].proto_write(&mut b);
+
+        assert_eq!(&[0, 0, 0, 3, b'a', b'b', b'c', 0, 0, 0, 1, 7], b.as_slice());
+
+        assert_eq!("abc", String::proto_read(&mut b));
+        assert_eq!(vec![7u8], Vec::proto_read(&mut b));
+
+        let mut b = Buffer::new();
+        b.len_width = LenWidth::U16;
+        b.write_iter(&mut vec![1u8, 2].into_iter());
+
+        assert_eq!(&[0, 2, 1, 2], b.as_slice());
+    }
+
+    #[test]
+    fn cursors() {
+        let den = User { name: String::from("Den"), email: String::from("den@mail.com"), age: 37 };
+        let ann = User { name: String::from("Ann"), email: String::from("ann@mail.com"), age: 30 };
+
+        // reading and appending at the same time, like a stream
+        let mut b = Buffer::new();
+        den.proto_write(&mut b);
+        ann.proto_write(&mut b);
+
+        assert_eq!(den, User::proto_read(&mut b));
+        den.proto_write(&mut b);
+        assert_eq!(ann, User::proto_read(&mut b));
+
+        let (len, consumed) = (b.len(), b.pos);
+        b.compact();
+        assert_eq!(0, b.pos);
+        assert_eq!(len - consumed, b.len());
+        assert_eq!(b.len(), b.write_pos());
+        assert_eq!(den, User::proto_read(&mut b));
+        assert_eq!(0, b.remaining());
+
+        b.compact();
+        assert!(b.is_empty());
+
+        // seek_write overwrites in place
+        let mut b = Buffer::from_vec(vec![1, 2, 3]);
+        b.seek_write(1).unwrap();
+        9u8.proto_write(&mut b);
+        assert_eq!(&[1, 9, 3], b.as_slice());
+        assert_eq!(1, u8::proto_read(&mut b));
+        assert_eq!(Err(EncodeError::OutOfBounds { offset: 4, len: 3 }), b.seek_write(4));
+
+        // shared mode reads and writes at pos
+        b.cursor = Cursor::Shared;
+        assert_eq!(1, b.write_pos());
+        7u8.proto_write(&mut b);
+        assert_eq!(&[1, 7, 3], b.as_slice());
+        assert_eq!(3, u8::proto_read(&mut b));
+    }
+
+    #[test]
+    fn write_modes() {
+        let den = User { name: String::from("Den"), email: String::from("den@mail.com"), age: 37 };
+
+        // splice a field into the middle of an encoded message
+        let mut b = Buffer::new();
+        1u16.proto_write(&mut b);
+        3u16.proto_write(&mut b);
+
+        b.write_mode = WriteMode::Insert;
+        b.seek_write(2).unwrap();
+        2u16.proto_write(&mut b);
+        den.proto_write(&mut b);
+        assert_eq!(b.len() - 2, b.write_pos());
+
+        assert_eq!(1, u16::proto_read(&mut b));
+        assert_eq!(2, u16::proto_read(&mut b));
+        assert_eq!(den, User::proto_read(&mut b));
+        assert_eq!(3, u16::proto_read(&mut b));
+
+        // the same splice with the tail moved once for the whole user
+        let mut staged = Buffer::new();
+        1u16.proto_write(&mut staged);
+        3u16.proto_write(&mut staged);
+
+        staged.write_mode = WriteMode::Insert;
+        staged.seek_write(2).unwrap();
+        2u16.proto_write(&mut staged);
+        staged.write_value(&den);
+        assert_eq!(b.as_slice(), staged.as_slice());
+        assert_eq!(b.len() - 2, staged.write_pos());
+
+        staged.len_width = LenWidth::U16;
+        assert_eq!(
+            Err(EncodeError::LengthOverflow { offset: b.len() - 2, len: 1 << 16, len_width: LenWidth::U16 }),
+            staged.try_write_value(&vec![0u8; 1 << 16])
+        );
+        assert_eq!(b.as_slice(), staged.as_slice());
+
+        // inserting before the read position keeps the unread bytes in view
+        let mut b = Buffer::from_vec(vec![1, 2, 3]);
+        b.write_mode = WriteMode::Insert;
+        b.pos = 2;
+        b.seek_write(1).unwrap();
+        9u8.proto_write(&mut b);
+        assert_eq!(&[1, 9, 2, 3], b.as_slice());
+        assert_eq!(3, b.pos);
+
+        // overwriting keeps the length and grows at the end
+        let mut b = Buffer::from_vec(vec![1, 2, 3]);
+        b.seek_write(1).unwrap();
+        [7u8, 8, 9].proto_write(&mut b);
+        assert_eq!(&[1, 7, 8, 9], b.as_slice());
+
+        let mut b = Buffer::from_vec(vec![1, 2, 3]);
+        b.write_mode = WriteMode::AppendOnly;
+        4u8.proto_write(&mut b);
+        b.seek_write(1).unwrap();
+        assert_eq!(Err(EncodeError::NotAppending { offset: 1, len: 4 }), 5u8.try_proto_write(&mut b));
+        assert_eq!(Err(EncodeError::NotAppending { offset: 1, len: 4 }), den.try_proto_write(&mut b));
+        assert_eq!(&[1, 2, 3, 4], b.as_slice());
+
+        let mut b = Buffer::new();
+        b.cursor = Cursor::Shared;
+        b.pos = 2;
+        assert_eq!(Err(EncodeError::OutOfBounds { offset: 2, len: 0 }), "abc".try_proto_write(&mut b));
+
+        let mut b = Buffer::new();
+        b.len_width = LenWidth::U16;
+        assert_eq!(
+            Err(EncodeError::LengthOverflow { offset: 0, len: 1 << 16, len_width: LenWidth::U16 }),
+            vec![0u8; 1 << 16].try_proto_write(&mut b)
+        );
+
+        // a value that fails inside a length prefix leaves nothing behind
+        let mut b = Buffer::new();
+        b.len_width = LenWidth::U16;
+        1u8.proto_write(&mut b);
+        assert_eq!(
+            Err(EncodeError::LengthOverflow { offset: 3, len: 1 << 16, len_width: LenWidth::U16 }),
+            b.try_write_len_prefixed(|buf| vec![0u8; 1 << 16].try_proto_write(buf))
+        );
+        2u8.proto_write(&mut b);
+        assert_eq!(&[1, 2], b.as_slice());
+        assert_eq!(1, u8::proto_read(&mut b));
+        assert_eq!(2, u8::proto_read(&mut b));
+
+        let mut b = Buffer::from_vec(vec![1, 2]);
+        b.write_mode = WriteMode::Insert;
+        b.len_width = LenWidth::U16;
+        b.seek_write(1).unwrap();
+        b.pos = 1;
+        assert!(b.try_write_len_prefixed(|buf| vec![0u8; 1 << 16].try_proto_write(buf)).is_err());
+        assert_eq!(&[1, 2], b.as_slice());
+        assert_eq!((1, 1), (b.pos, b.write_pos()));
+        assert_eq!(2, u8::proto_read(&mut b));
+    }
+
+    #[test]
+    fn nested_len_prefix() {
+        // write_iter after other data patches its own count
+        let mut b = Buffer::new();
+        "abc".proto_write(&mut b);
+        b.write_iter(&mut [1u16, 2].iter());
+        b.write_iter(&mut ["x", "y", "z"].iter());
+
+        assert_eq!("abc", String::proto_read(&mut b));
+        assert_eq!(vec![1u16, 2], Vec::proto_read(&mut b));
+        assert_eq!(vec!["x", "y", "z"], Vec::<String>::proto_read(&mut b));
+
+        // varint lengths outgrow their one byte placeholder
+        for encoding in [Encoding::Fixed, Encoding::Varint].iter() {
+            let mut b = Buffer::new();
+            b.encoding = *encoding;
+            7u8.proto_write(&mut b);
+
+            let outer = b.begin_len_prefix().unwrap();
+            b.try_write_len_prefixed(|buf| {
+                let items = buf.begin_len_prefix()?;
+                (0..200u32).for_each(|i| i.proto_write(buf));
+                buf.patch_len(items, 200)
+            }).unwrap();
+            b.write_iter(&mut (0..300u16).map(|i| i * 3));
+            b.end_len_prefix(outer).unwrap();
+            8u8.proto_write(&mut b);
+
+            assert_eq!(7, u8::proto_read(&mut b));
+            b.try_read_len_prefixed(|buf| {
+                let inner = buf.try_read_len_prefixed(Vec::<u32>::try_proto_read)?;
+                assert_eq!((0..200).collect::<Vec<_>>(), inner);
+                assert_eq!((0..300).map(|i| i * 3).collect::<Vec<u16>>(), Vec::proto_read(buf));
+                Ok(())
+            }).unwrap();
+            assert_eq!(8, u8::proto_read(&mut b));
+            assert_eq!(0, b.remaining());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit into U16 prefix")]
+    fn len_width_overflow() {
+        let mut b = Buffer::new();
+        b.len_width = LenWidth::U16;
+
+        vec![0u8; u16::MAX as usize + 1].proto_write(&mut b);
+    }
+
+    #[test]
+    fn decode_errors() {
+        let mut b = Buffer::new();
+
+        7u8.proto_write(&mut b);
+        0xd800u32.proto_write(&mut b);
+
+        assert_eq!(Err(DecodeError::InvalidTag { offset: 0, tag: 7 }), Option::<u8>::try_proto_read(&mut b));
+
+        b.pos = 1;
+        assert_eq!(Err(DecodeError::InvalidChar { offset: 1, value: 0xd800 }), char::try_proto_read(&mut b));
+
+        b.pos = 3;
+        assert_eq!(Err(DecodeError::UnexpectedEof { offset: 3, needed: 2 }), u32::try_proto_read(&mut b));
+        assert_eq!(3, b.pos);
+
+        let mut b = Buffer::new();
+        b.cursor = Cursor::Shared;
+        b.write_utf8("abc");
+        b.pos = 8;
+        0xffu8.proto_write(&mut b);
+
+        b.pos = 0;
+        assert_eq!(Err(DecodeError::InvalidUtf8 { offset: 8 }), String::try_proto_read(&mut b));
+
+        b.pos = 0;
+        usize::MAX.proto_write(&mut b);
+        b.pos = 0;
+        assert!(matches!(Vec::<u8>::try_proto_read(&mut b), Err(DecodeError::UnexpectedEof { .. })));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid tag 2 at 0")]
+    fn proto_read_panics() {
+        let mut b = Buffer::new();
+        2u8.proto_write(&mut b);
+
+        Option::<u8>::proto_read(&mut b);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn io_sink_source() {
+        use std::io::{self, Read, Write};
+
+        // hands out a few bytes per call and is interrupted once
+        struct Trickle<'a> { data: &'a [u8], interrupted: bool }
+
+        impl<'a> Read for Trickle<'a> {
+            fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+                if !self.interrupted {
+                    self.interrupted = true;
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+
+                let n = self.data.len().min(out.len()).min(3);
+                out[..n].copy_from_slice(&self.data[..n]);
+                self.data = &self.data[n..];
+
+                Ok(n)
+            }
+        }
+
+        struct Broken;
+
+        impl Read for Broken {
+            fn read(&mut self, _out: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::ConnectionReset.into())
+            }
+        }
+
+        impl Write for Broken {
+            fn write(&mut self, _v: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // the varint length of the users is longer than its placeholder and spans several chunks
+        fn write<S: ProtoSink>(users: &[User], buf: &mut S) -> Result<(), EncodeError> {
+            7u8.try_proto_write(buf)?;
+            buf.try_write_len_prefixed(|buf| users.try_proto_write(buf))?;
+            "end".try_proto_write(buf)
+        }
+
+        let users = (0..1000)
+            .map(|i| User { name: format!("user{}", i), email: String::from("den@mail.com"), age: (i % 100) as u8 })
+            .collect::<Vec<_>>();
+
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Varint;
+        write(&users, &mut b).unwrap();
+
+        let mut sink = IoSink::new(Vec::new());
+        sink.encoding = Encoding::Varint;
+        write(&users, &mut sink).unwrap();
+        assert_eq!(b.len(), sink.write_pos());
+
+        let data = sink.into_inner().unwrap();
+        assert_eq!(b.as_slice(), &data[..]);
+
+        let mut src = IoSource::new(Trickle { data: &data, interrupted: false });
+        src.encoding = Encoding::Varint;
+
+        assert_eq!(7, u8::proto_read(&mut src));
+        assert_eq!(users, src.try_read_len_prefixed(Vec::<User>::try_proto_read).unwrap());
+        assert_eq!("end", String::proto_read(&mut src));
+        assert_eq!(data.len(), src.pos());
+        assert_eq!(Err(DecodeError::UnexpectedEof { offset: data.len(), needed: 1 }), u8::try_proto_read(&mut src));
+
+        let mut src = IoSource::new(&data[..data.len() - 1]);
+        src.encoding = Encoding::Varint;
+        src.try_read_exact(data.len() - 4).unwrap();
+        assert_eq!(Err(DecodeError::UnexpectedEof { offset: data.len() - 3, needed: 1 }), String::try_proto_read(&mut src));
+
+        // io errors carry the stream offset
+        let mut sink = IoSink::new(Broken);
+        "abc".proto_write(&mut sink);
+        assert_eq!(Err(EncodeError::Io { offset: 0, kind: io::ErrorKind::BrokenPipe }), sink.flush());
+
+        let mut src = IoSource::new(Broken);
+        assert_eq!(Err(DecodeError::Io { offset: 0, kind: io::ErrorKind::ConnectionReset }), u32::try_proto_read(&mut src));
+
+        // a value that fails inside a length prefix is dropped, the rest still reaches the writer
+        let mut sink = IoSink::new(Vec::new());
+        sink.len_width = LenWidth::U16;
+        1u8.proto_write(&mut sink);
+        assert_eq!(
+            Err(EncodeError::LengthOverflow { offset: 3, len: 1 << 16, len_width: LenWidth::U16 }),
+            sink.try_write_len_prefixed(|buf| vec![0u8; 1 << 16].try_proto_write(buf))
+        );
+        2u8.proto_write(&mut sink);
+        assert_eq!(vec![1, 2], sink.into_inner().unwrap());
+
+        let mut sink = IoSink::new(Vec::new());
+        1u8.proto_write(&mut sink);
+        let prefix = sink.begin_len_prefix().unwrap();
+        assert_eq!(Err(EncodeError::UnfinishedLenPrefix { offset: 1 }), sink.flush());
+        sink.end_len_prefix(prefix).unwrap();
+        sink.flush().unwrap();
+        assert_eq!(&[1, 0, 0, 0, 0, 0, 0, 0, 0], &sink.get_ref()[..]);
+
+        // a source that can not check lengths up front must not overflow on broken ones
+        let data = [0xff; 16];
+        let invalid = DecodeError::InvalidLength { offset: 0, len: usize::MAX };
+
+        let mut src = IoSource::new(&data[..]);
+        assert_eq!(Err(invalid), src.try_read_len_prefixed(u8::try_proto_read));
+
+        let mut src = IoSource::new(&data[..]);
+        assert_eq!(Err(invalid), src.try_read_fields(|_, src| src.try_skip_len_prefixed()));
+
+        let data = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        let mut src = IoSource::new(&data[..]);
+        let mut packed = Vec::<u32>::new();
+        assert_eq!(Err(invalid), protobuf::FieldReader::merge_field(&mut packed, protobuf::WireType::LengthDelimited, &mut src));
+    }
+
+    #[test]
+    fn slice_sink() {
+        let den = User { name: String::from("Den"), email: String::from("den@mail.com"), age: 37 };
+
+        let mut b = Buffer::new();
+        den.proto_write(&mut b);
+
+        let mut out = [0u8; 64];
+        assert_eq!(Ok(b.len()), encode_into(&den, &mut out));
+        assert_eq!(b.as_slice(), &out[..b.len()]);
+
+        let mut out = vec![0u8; b.len() - 1];
+        assert_eq!(Err(EncodeError::InsufficientSpace { offset: b.len() - 1, needed: 1 }), encode_into(&den, &mut out));
+
+        // the patched varint length needs one more byte than its placeholder
+        let mut b = Buffer::new();
+        b.encoding = Encoding::Varint;
+        b.try_write_len_prefixed(|buf| buf.try_write_bytes(&[7; 200])).unwrap();
+        assert_eq!(202, b.len());
+
+        let mut out = [0u8; 202];
+        let mut sink = SliceSink::new(&mut out);
+        sink.encoding = Encoding::Varint;
+        sink.try_write_len_prefixed(|buf| buf.try_write_bytes(&[7; 200])).unwrap();
+        assert_eq!(b.as_slice(), sink.as_slice());
+
+        let mut out = [0u8; 8];
+        let mut sink = SliceSink::new(&mut out);
+        sink.encoding = Encoding::Varint;
+        1u8.proto_write(&mut sink);
+        assert_eq!(
+            Err(EncodeError::InsufficientSpace { offset: 2, needed: 2 }),
+            sink.try_write_len_prefixed(|buf| buf.try_write_bytes(&[7; 8]))
+        );
+        assert_eq!(&[1], sink.as_slice());
+
+        let mut out = [0u8; 201];
+        let mut sink = SliceSink::new(&mut out);
+        sink.encoding = Encoding::Varint;
+        assert_eq!(
+            Err(EncodeError::InsufficientSpace { offset: 201, needed: 1 }),
+            sink.try_write_len_prefixed(|buf| buf.try_write_bytes(&[7; 200]))
+        );
+    }
 }
 
@@ -0,0 +1,421 @@
+//! Google Protocol Buffers wire format.
+//!
+//! Rust types map to protobuf scalar types as follows: `bool`, `u8`..`u64`, `usize` and `char` are
+//! `uint32`/`uint64`, `i8`..`i64` are `int32`/`int64`, `f32` is `float`, `f64` is `double`,
+//! `String` is `string`, `Bytes` is `bytes`. `Option<T>` is an optional field, `Vec<T>` a repeated one
+//! (packed for scalars, so `Vec<u8>` is a packed `uint32`, not `bytes`), and structs deriving with
+//! `#[proto(field = N)]` attributes are nested messages.
+//!
+//! Through `ProtoWriter`/`ProtoReader` a message is length-delimited, so it can sit inside a `Vec`, an
+//! `Option` or another struct. A top level message is just its fields up to the end of the data, it
+//! is written and read with `try_write_message` and `try_read_message`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use super::{DecodeError, EncodeError, ProtoReader, ProtoSink, ProtoSource, ProtoWriter};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum WireType {
+    Varint = 0,
+    Fixed64 = 1,
+    LengthDelimited = 2,
+    Fixed32 = 5
+}
+
+impl WireType {
+    fn from_u8(v: u8) -> Option<WireType> {
+        match v {
+            0 => Some(WireType::Varint),
+            1 => Some(WireType::Fixed64),
+            2 => Some(WireType::LengthDelimited),
+            5 => Some(WireType::Fixed32),
+            _ => None
+        }
+    }
+}
+
+pub trait ValueWriter {
+    const WIRE_TYPE: WireType;
+
+    fn try_write_value<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError>;
+}
+
+pub trait ValueReader: Sized {
+    const WIRE_TYPE: WireType;
+
+    fn try_read_value<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError>;
+}
+
+pub trait FieldWriter {
+    fn try_write_field<S: ProtoSink>(&self, number: u32, buf: &mut S) -> Result<(), EncodeError>;
+}
+
+pub trait FieldReader {
+    fn merge_field<S: ProtoSource>(&mut self, wire_type: WireType, buf: &mut S) -> Result<(), DecodeError>;
+}
+
+pub trait MessageWriter {
+    fn try_write_fields<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError>;
+}
+
+pub trait MessageReader: Sized {
+    fn try_read_fields<S: ProtoSource>(buf: &mut S, end: usize) -> Result<Self, DecodeError>;
+}
+
+pub fn try_write_message<T: MessageWriter + ?Sized, S: ProtoSink>(v: &T, buf: &mut S) -> Result<(), EncodeError> {
+    v.try_write_fields(buf)
+}
+
+// the message runs to the end of the source
+pub fn try_read_message<T: MessageReader, S: ProtoSource>(buf: &mut S) -> Result<T, DecodeError> {
+    let end = buf.pos() + buf.try_remaining()?;
+
+    T::try_read_fields(buf, end)
+}
+
+pub fn try_write_key<S: ProtoSink>(buf: &mut S, number: u32, wire_type: WireType) -> Result<(), EncodeError> {
+    buf.try_write_varint(((number as u64) << 3) | wire_type as u64)
+}
+
+pub fn try_read_key<S: ProtoSource>(buf: &mut S) -> Result<(u32, WireType), DecodeError> {
+    let offset = buf.pos();
+    let key = buf.try_read_varint()?;
+
+    let number = key >> 3;
+
+    if number == 0 || number > u32::MAX as u64 {
+        return Err(DecodeError::InvalidKey { offset, key });
+    }
+
+    match WireType::from_u8((key & 0x07) as u8) {
+        Some(wire_type) => Ok((number as u32, wire_type)),
+        None => Err(DecodeError::InvalidWireType { offset, wire_type: (key & 0x07) as u8 })
+    }
+}
+
+pub fn skip_field<S: ProtoSource>(wire_type: WireType, buf: &mut S) -> Result<(), DecodeError> {
+    match wire_type {
+        WireType::Varint => { buf.try_read_varint()?; }
+        WireType::Fixed64 => { buf.try_read_exact(8)?; }
+        WireType::Fixed32 => { buf.try_read_exact(4)?; }
+        WireType::LengthDelimited => {
+            let len = try_read_len(buf)?;
+            buf.try_read_exact(len)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn try_read_len<S: ProtoSource>(buf: &mut S) -> Result<usize, DecodeError> {
+    let offset = buf.pos();
+    let len = buf.try_read_varint()?;
+
+    len.try_into().map_err(|_| DecodeError::LengthOverflow { offset, len })
+}
+
+// runs `read` over a length-delimited payload and checks that it consumed exactly that payload
+fn try_read_delimited<S, T, F>(buf: &mut S, read: F) -> Result<T, DecodeError>
+    where S: ProtoSource, F: FnOnce(&mut S, usize) -> Result<T, DecodeError>
+{
+    let offset = buf.pos();
+    let len = try_read_len(buf)?;
+    buf.check_remaining(len)?;
+
+    let end = super::stream::payload_end(buf.pos(), offset, len)?;
+    let v = read(buf, end)?;
+
+    if buf.pos() != end {
+        return Err(DecodeError::InvalidLength { offset, len });
+    }
+
+    Ok(v)
+}
+
+// the length is back-patched by the sink, always as a varint whatever the sink settings are
+fn try_write_delimited<S, F>(buf: &mut S, write: F) -> Result<(), EncodeError>
+    where S: ProtoSink, F: FnOnce(&mut S) -> Result<(), EncodeError>
+{
+    let prefix = buf.begin_varint_len_prefix()?;
+    super::stream::finish_len_prefixed(buf, prefix, write)
+}
+
+fn try_read_checked<T: ValueReader, S: ProtoSource>(wire_type: WireType, buf: &mut S) -> Result<T, DecodeError> {
+    if wire_type != T::WIRE_TYPE {
+        return Err(DecodeError::InvalidWireType { offset: buf.pos(), wire_type: wire_type as u8 });
+    }
+
+    T::try_read_value(buf)
+}
+
+macro_rules! impl_varint_value {
+    ($($t:ty), +) => {
+        $(impl ValueWriter for $t {
+            const WIRE_TYPE: WireType = WireType::Varint;
+
+            fn try_write_value<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+                buf.try_write_varint(*self as u64)
+            }
+        }
+
+        impl ValueReader for $t {
+            const WIRE_TYPE: WireType = WireType::Varint;
+
+            fn try_read_value<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+                let offset = buf.pos();
+
+                buf.try_read_varint()?.try_into().map_err(|_| DecodeError::InvalidVarint { offset })
+            }
+        })*
+    }
+}
+
+// int32 and int64 are sign extended to ten bytes, not zigzag encoded
+macro_rules! impl_signed_varint_value {
+    ($($t:ty), +) => {
+        $(impl ValueWriter for $t {
+            const WIRE_TYPE: WireType = WireType::Varint;
+
+            fn try_write_value<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+                buf.try_write_varint(*self as i64 as u64)
+            }
+        }
+
+        impl ValueReader for $t {
+            const WIRE_TYPE: WireType = WireType::Varint;
+
+            fn try_read_value<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+                let offset = buf.pos();
+
+                (buf.try_read_varint()? as i64).try_into().map_err(|_| DecodeError::InvalidVarint { offset })
+            }
+        })*
+    }
+}
+
+macro_rules! impl_fixed_value {
+    ($($t:ty => $wire_type:expr), +) => {
+        $(impl ValueWriter for $t {
+            const WIRE_TYPE: WireType = $wire_type;
+
+            fn try_write_value<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+                buf.try_write_bytes(&self.to_le_bytes())
+            }
+        }
+
+        impl ValueReader for $t {
+            const WIRE_TYPE: WireType = $wire_type;
+
+            fn try_read_value<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+                let bytes = buf.try_read_exact(core::mem::size_of::<$t>())?;
+
+                Ok(<$t>::from_le_bytes(bytes.try_into().unwrap()))
+            }
+        })*
+    }
+}
+
+impl_varint_value! (u8, u16, u32, u64, usize);
+impl_signed_varint_value! (i8, i16, i32, i64);
+impl_fixed_value! (f32 => WireType::Fixed32, f64 => WireType::Fixed64);
+
+impl ValueWriter for bool {
+    const WIRE_TYPE: WireType = WireType::Varint;
+
+    fn try_write_value<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_varint(*self as u64)
+    }
+}
+
+impl ValueReader for bool {
+    const WIRE_TYPE: WireType = WireType::Varint;
+
+    fn try_read_value<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        Ok(buf.try_read_varint()? != 0)
+    }
+}
+
+impl ValueWriter for char {
+    const WIRE_TYPE: WireType = WireType::Varint;
+
+    fn try_write_value<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_varint(*self as u64)
+    }
+}
+
+impl ValueReader for char {
+    const WIRE_TYPE: WireType = WireType::Varint;
+
+    fn try_read_value<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let offset = buf.pos();
+        let value = u32::try_read_value(buf)?;
+
+        core::char::from_u32(value).ok_or(DecodeError::InvalidChar { offset, value })
+    }
+}
+
+impl ValueWriter for &str {
+    const WIRE_TYPE: WireType = WireType::LengthDelimited;
+
+    fn try_write_value<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_varint(self.len() as u64)?;
+        buf.try_write_bytes(self.as_bytes())
+    }
+}
+
+impl ValueWriter for String {
+    const WIRE_TYPE: WireType = WireType::LengthDelimited;
+
+    fn try_write_value<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        self.as_str().try_write_value(buf)
+    }
+}
+
+impl ValueReader for String {
+    const WIRE_TYPE: WireType = WireType::LengthDelimited;
+
+    fn try_read_value<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let len = try_read_len(buf)?;
+        let offset = buf.pos();
+
+        match core::str::from_utf8(buf.try_read_exact(len)?) {
+            Ok(s) => Ok(String::from(s)),
+            Err(_) => Err(DecodeError::InvalidUtf8 { offset })
+        }
+    }
+}
+
+// protobuf `bytes`, in the other encodings it has the layout of Vec<u8>
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Bytes(pub Vec<u8>);
+
+impl From<Vec<u8>> for Bytes {
+    fn from(v: Vec<u8>) -> Self {
+        Bytes(v)
+    }
+}
+
+impl ValueWriter for Bytes {
+    const WIRE_TYPE: WireType = WireType::LengthDelimited;
+
+    fn try_write_value<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        buf.try_write_varint(self.0.len() as u64)?;
+        buf.try_write_bytes(&self.0)
+    }
+}
+
+impl ValueReader for Bytes {
+    const WIRE_TYPE: WireType = WireType::LengthDelimited;
+
+    fn try_read_value<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        let len = try_read_len(buf)?;
+
+        Ok(Bytes(buf.try_read_exact(len)?.to_vec()))
+    }
+}
+
+impl ProtoWriter for Bytes {
+    fn try_proto_write<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        self.0.try_proto_write(buf)
+    }
+}
+
+impl ProtoReader for Bytes {
+    fn try_proto_read<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        Ok(Bytes(Vec::try_proto_read(buf)?))
+    }
+}
+
+impl<T: MessageWriter> ValueWriter for T {
+    const WIRE_TYPE: WireType = WireType::LengthDelimited;
+
+    fn try_write_value<S: ProtoSink>(&self, buf: &mut S) -> Result<(), EncodeError> {
+        try_write_delimited(buf, |payload| self.try_write_fields(payload))
+    }
+}
+
+impl<T: MessageReader> ValueReader for T {
+    const WIRE_TYPE: WireType = WireType::LengthDelimited;
+
+    fn try_read_value<S: ProtoSource>(buf: &mut S) -> Result<Self, DecodeError> {
+        try_read_delimited(buf, T::try_read_fields)
+    }
+}
+
+impl<T: ValueWriter> FieldWriter for T {
+    fn try_write_field<S: ProtoSink>(&self, number: u32, buf: &mut S) -> Result<(), EncodeError> {
+        try_write_key(buf, number, T::WIRE_TYPE)?;
+        self.try_write_value(buf)
+    }
+}
+
+impl<T: ValueReader> FieldReader for T {
+    fn merge_field<S: ProtoSource>(&mut self, wire_type: WireType, buf: &mut S) -> Result<(), DecodeError> {
+        *self = try_read_checked(wire_type, buf)?;
+
+        Ok(())
+    }
+}
+
+impl<T: ValueWriter> FieldWriter for Option<T> {
+    fn try_write_field<S: ProtoSink>(&self, number: u32, buf: &mut S) -> Result<(), EncodeError> {
+        match self {
+            Some(v) => v.try_write_field(number, buf),
+            None => Ok(())
+        }
+    }
+}
+
+impl<T: ValueReader> FieldReader for Option<T> {
+    fn merge_field<S: ProtoSource>(&mut self, wire_type: WireType, buf: &mut S) -> Result<(), DecodeError> {
+        *self = Some(try_read_checked(wire_type, buf)?);
+
+        Ok(())
+    }
+}
+
+impl<T: ValueWriter> FieldWriter for Vec<T> {
+    fn try_write_field<S: ProtoSink>(&self, number: u32, buf: &mut S) -> Result<(), EncodeError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        if T::WIRE_TYPE == WireType::LengthDelimited {
+            for v in self.iter() {
+                v.try_write_field(number, buf)?;
+            }
+
+            Ok(())
+        } else {
+            try_write_key(buf, number, WireType::LengthDelimited)?;
+            try_write_delimited(buf, |payload| {
+                for v in self.iter() {
+                    v.try_write_value(payload)?;
+                }
+
+                Ok(())
+            })
+        }
+    }
+}
+
+impl<T: ValueReader> FieldReader for Vec<T> {
+    fn merge_field<S: ProtoSource>(&mut self, wire_type: WireType, buf: &mut S) -> Result<(), DecodeError> {
+        // scalars may arrive both packed and one per field
+        if wire_type == WireType::LengthDelimited && T::WIRE_TYPE != WireType::LengthDelimited {
+            try_read_delimited(buf, |buf, end| {
+                while buf.pos() < end {
+                    self.push(T::try_read_value(buf)?);
+                }
+
+                Ok(())
+            })
+        } else {
+            self.push(try_read_checked(wire_type, buf)?);
+
+            Ok(())
+        }
+    }
+}
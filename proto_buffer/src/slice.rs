@@ -0,0 +1,93 @@
+use super::{EncodeError, Encoding, Endian, LenPrefix, LenWidth, ProtoSink, ProtoWriter};
+
+// encodes into a caller-provided slice, nothing is allocated and running out of room is an error
+pub struct SliceSink<'a> {
+    data: &'a mut [u8],
+    pos: usize,
+    pub endian: Endian,
+    pub encoding: Encoding,
+    pub len_width: LenWidth,
+}
+
+impl<'a> SliceSink<'a> {
+    pub fn new(data: &'a mut [u8]) -> SliceSink<'a> {
+        SliceSink {
+            data,
+            pos: 0,
+            endian: Endian::BigEndian,
+            encoding: Encoding::Fixed,
+            len_width: LenWidth::U64
+        }
+    }
+
+    // the bytes written so far
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..self.pos]
+    }
+
+    fn check_space(&self, offset: usize, len: usize) -> Result<(), EncodeError> {
+        if len > self.data.len() {
+            Err(EncodeError::InsufficientSpace { offset, needed: len - self.data.len() })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> ProtoSink for SliceSink<'a> {
+    fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn len_width(&self) -> LenWidth {
+        self.len_width
+    }
+
+    fn write_pos(&self) -> usize {
+        self.pos
+    }
+
+    fn try_write_bytes(&mut self, v: &[u8]) -> Result<(), EncodeError> {
+        let end = self.pos + v.len();
+        self.check_space(self.pos, end)?;
+
+        self.data[self.pos..end].copy_from_slice(v);
+        self.pos = end;
+
+        Ok(())
+    }
+
+    fn patch_len(&mut self, prefix: LenPrefix, len: usize) -> Result<(), EncodeError> {
+        let (bytes, n) = super::stream::encode_prefix_len(self, &prefix, len)?;
+        let slot = prefix.payload_start - prefix.start;
+
+        // a longer varint moves the payload along in place
+        if n != slot {
+            let end = self.pos + n - slot;
+            self.check_space(self.pos, end)?;
+
+            self.data.copy_within(prefix.payload_start..self.pos, prefix.start + n);
+            self.pos = end;
+        }
+
+        self.data[prefix.start..prefix.start + n].copy_from_slice(&bytes[..n]);
+
+        Ok(())
+    }
+
+    fn abort_len_prefix(&mut self, prefix: LenPrefix) {
+        self.pos = prefix.start;
+    }
+}
+
+// encodes `v` with the default settings and returns the number of bytes written
+pub fn encode_into<T: ProtoWriter + ?Sized>(v: &T, out: &mut [u8]) -> Result<usize, EncodeError> {
+    let mut sink = SliceSink::new(out);
+    v.try_proto_write(&mut sink)?;
+
+    Ok(sink.pos)
+}
@@ -0,0 +1,423 @@
+use alloc::vec::Vec;
+
+use super::{Buffer, BufferView, Cursor, DecodeError, EncodeError, Encoding, Endian, LenWidth, ProtoReader, ProtoWriter, WriteMode};
+
+// where encoded bytes go: a Buffer, an io::Write through IoSink, ...
+pub trait ProtoSink: Sized {
+    fn endian(&self) -> Endian;
+    fn encoding(&self) -> Encoding;
+    fn len_width(&self) -> LenWidth;
+
+    // the position of the next byte, error offsets and length prefixes are relative to it
+    fn write_pos(&self) -> usize;
+
+    fn try_write_bytes(&mut self, v: &[u8]) -> Result<(), EncodeError>;
+
+    // replaces the placeholder written by begin_len_prefix, the bytes after it may have to move
+    fn patch_len(&mut self, prefix: LenPrefix, len: usize) -> Result<(), EncodeError>;
+
+    fn try_write_u8(&mut self, v: u8) -> Result<(), EncodeError> {
+        self.try_write_bytes(&[v])
+    }
+
+    fn try_write_varint(&mut self, v: u64) -> Result<(), EncodeError> {
+        let (bytes, len) = encode_varint(v);
+
+        self.try_write_bytes(&bytes[..len])
+    }
+
+    fn try_write_len(&mut self, len: usize) -> Result<(), EncodeError> {
+        let (bytes, n) = encode_len(self, len, self.write_pos())?;
+
+        self.try_write_bytes(&bytes[..n])
+    }
+
+    fn try_write_utf8(&mut self, v: &str) -> Result<(), EncodeError> {
+        self.try_write_len(v.len())?;
+        self.try_write_bytes(v.as_bytes())
+    }
+
+    fn try_write_len_prefixed_bytes(&mut self, v: &[u8]) -> Result<(), EncodeError> {
+        self.try_write_len(v.len())?;
+        self.try_write_bytes(v)
+    }
+
+    // writes a zero length as a placeholder, end_len_prefix or patch_len replaces it later
+    fn begin_len_prefix(&mut self) -> Result<LenPrefix, EncodeError> {
+        let start = self.write_pos();
+        self.try_write_len(0)?;
+
+        Ok(LenPrefix { start, payload_start: self.write_pos(), varint: false })
+    }
+
+    // like begin_len_prefix, but the length is a varint whatever the encoding and len_width,
+    // as protobuf needs
+    fn begin_varint_len_prefix(&mut self) -> Result<LenPrefix, EncodeError> {
+        let start = self.write_pos();
+        self.try_write_u8(0)?;
+
+        Ok(LenPrefix { start, payload_start: self.write_pos(), varint: true })
+    }
+
+    // patches the number of bytes written since begin_len_prefix
+    fn end_len_prefix(&mut self, prefix: LenPrefix) -> Result<(), EncodeError> {
+        let len = self.write_pos().saturating_sub(prefix.payload_start);
+        self.patch_len(prefix, len)
+    }
+
+    // gives up a prefix whose payload failed to encode, sinks that can drop the partial bytes do so
+    fn abort_len_prefix(&mut self, _prefix: LenPrefix) {}
+
+    fn try_write_len_prefixed<F>(&mut self, write: F) -> Result<(), EncodeError>
+        where F: FnOnce(&mut Self) -> Result<(), EncodeError>
+    {
+        let prefix = self.begin_len_prefix()?;
+        finish_len_prefixed(self, prefix, write)
+    }
+
+    // evolvable structs are a length-prefixed list of (field id, length-prefixed value) entries
+    fn try_write_field<F>(&mut self, id: u32, write: F) -> Result<(), EncodeError>
+        where F: FnOnce(&mut Self) -> Result<(), EncodeError>
+    {
+        id.try_proto_write(self)?;
+        self.try_write_len_prefixed(write)
+    }
+}
+
+// where encoded bytes come from: a Buffer, a BufferView, an io::Read through IoSource, ...
+pub trait ProtoSource: Sized {
+    fn endian(&self) -> Endian;
+    fn encoding(&self) -> Encoding;
+    fn len_width(&self) -> LenWidth;
+
+    // the position of the next byte, error offsets are relative to it
+    fn pos(&self) -> usize;
+
+    // bytes that can be read without waiting for more input, caps preallocation for untrusted lengths
+    fn available(&self) -> usize;
+
+    fn try_read_exact(&mut self, len: usize) -> Result<&[u8], DecodeError>;
+
+    // bytes left before the source ends, streams have to read up to their end to tell
+    fn try_remaining(&mut self) -> Result<usize, DecodeError> {
+        Ok(self.available())
+    }
+
+    // fails early when `len` more bytes can not be there, sources that can not tell accept any length
+    fn check_remaining(&self, _len: usize) -> Result<(), DecodeError> {
+        Ok(())
+    }
+
+    fn try_read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.try_read_exact(1)?[0])
+    }
+
+    fn try_read_varint(&mut self) -> Result<u64, DecodeError> {
+        let offset = self.pos();
+
+        super::decode_varint(offset, || self.try_read_u8())
+    }
+
+    fn try_read_len(&mut self) -> Result<usize, DecodeError> {
+        use core::convert::TryInto;
+
+        let offset = self.pos();
+
+        let len = match self.len_width() {
+            LenWidth::U16 => u16::try_proto_read(self)? as u64,
+            LenWidth::U32 => u32::try_proto_read(self)? as u64,
+            LenWidth::U64 => u64::try_proto_read(self)?
+        };
+
+        len.try_into().map_err(|_| DecodeError::LengthOverflow { offset, len })
+    }
+
+    fn try_read_utf8(&mut self) -> Result<&str, DecodeError> {
+        let len = self.try_read_len()?;
+        let offset = self.pos();
+
+        core::str::from_utf8(self.try_read_exact(len)?).map_err(|_| DecodeError::InvalidUtf8 { offset })
+    }
+
+    // `read` has to consume exactly the prefixed length
+    fn try_read_len_prefixed<T, F>(&mut self, read: F) -> Result<T, DecodeError>
+        where F: FnOnce(&mut Self) -> Result<T, DecodeError>
+    {
+        let offset = self.pos();
+        let len = self.try_read_len()?;
+        self.check_remaining(len)?;
+
+        let end = payload_end(self.pos(), offset, len)?;
+        let v = read(self)?;
+
+        if self.pos() != end {
+            return Err(DecodeError::InvalidLength { offset, len });
+        }
+
+        Ok(v)
+    }
+
+    fn try_read_len_prefixed_bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let len = self.try_read_len()?;
+
+        Ok(self.try_read_exact(len)?.to_vec())
+    }
+
+    fn try_skip_len_prefixed(&mut self) -> Result<(), DecodeError> {
+        let len = self.try_read_len()?;
+        self.try_read_exact(len)?;
+
+        Ok(())
+    }
+
+    fn try_read_fields<F>(&mut self, mut read_field: F) -> Result<(), DecodeError>
+        where F: FnMut(u32, &mut Self) -> Result<(), DecodeError>
+    {
+        let offset = self.pos();
+        let len = self.try_read_len()?;
+        self.check_remaining(len)?;
+
+        let end = payload_end(self.pos(), offset, len)?;
+
+        while self.pos() < end {
+            let id = u32::try_proto_read(self)?;
+            read_field(id, self)?;
+        }
+
+        if self.pos() != end {
+            return Err(DecodeError::InvalidLength { offset, len });
+        }
+
+        Ok(())
+    }
+}
+
+// the slot reserved by begin_len_prefix, it has to be handed back to end_len_prefix or patch_len
+#[must_use]
+#[derive(Debug)]
+pub struct LenPrefix {
+    pub(crate) start: usize,
+    pub(crate) payload_start: usize,
+    pub(crate) varint: bool,
+}
+
+// writes the payload after an open prefix and closes it, or drops it when the payload fails
+pub(crate) fn finish_len_prefixed<S, F>(sink: &mut S, prefix: LenPrefix, write: F) -> Result<(), EncodeError>
+    where S: ProtoSink, F: FnOnce(&mut S) -> Result<(), EncodeError>
+{
+    match write(sink) {
+        Ok(()) => sink.end_len_prefix(prefix),
+        Err(e) => {
+            sink.abort_len_prefix(prefix);
+            Err(e)
+        }
+    }
+}
+
+// a length read from untrusted input must not overflow the position
+pub(crate) fn payload_end(pos: usize, offset: usize, len: usize) -> Result<usize, DecodeError> {
+    pos.checked_add(len).ok_or(DecodeError::InvalidLength { offset, len })
+}
+
+pub(crate) fn encode_varint(mut v: u64) -> ([u8; 10], usize) {
+    let mut bytes = [0u8; 10];
+    let mut len = 0;
+
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+
+        if v == 0 {
+            bytes[len] = byte;
+            len += 1;
+            break;
+        }
+
+        bytes[len] = byte | 0x80;
+        len += 1;
+    }
+
+    (bytes, len)
+}
+
+// a length as the sink's settings write it, without going through the sink
+pub(crate) fn encode_len<S: ProtoSink>(sink: &S, len: usize, offset: usize) -> Result<([u8; 10], usize), EncodeError> {
+    let len = len as u64;
+    let len_width = sink.len_width();
+
+    if len > len_width.max() {
+        return Err(EncodeError::LengthOverflow { offset, len, len_width });
+    }
+
+    if sink.encoding() != Encoding::Fixed {
+        return Ok(encode_varint(len));
+    }
+
+    let mut bytes = [0u8; 10];
+    let big_endian = sink.endian() == Endian::BigEndian;
+
+    let n = match len_width {
+        LenWidth::U16 => 2,
+        LenWidth::U32 => 4,
+        LenWidth::U64 => 8
+    };
+
+    let be = len.to_be_bytes();
+    let le = len.to_le_bytes();
+
+    if big_endian {
+        bytes[..n].copy_from_slice(&be[8 - n..]);
+    } else {
+        bytes[..n].copy_from_slice(&le[..n]);
+    }
+
+    Ok((bytes, n))
+}
+
+// the bytes that replace the placeholder of `prefix`
+pub(crate) fn encode_prefix_len<S: ProtoSink>(sink: &S, prefix: &LenPrefix, len: usize) -> Result<([u8; 10], usize), EncodeError> {
+    if prefix.varint {
+        Ok(encode_varint(len as u64))
+    } else {
+        encode_len(sink, len, prefix.start)
+    }
+}
+
+// patches a length slot inside `data`, which holds the stream from `base` on
+pub(crate) fn patch_len_in<S: ProtoSink>(sink: &S, data: &mut Vec<u8>, base: usize, prefix: &LenPrefix, len: usize) -> Result<isize, EncodeError> {
+    let (bytes, n) = encode_prefix_len(sink, prefix, len)?;
+    let (start, payload_start) = (prefix.start - base, prefix.payload_start - base);
+
+    if n == payload_start - start {
+        data[start..payload_start].copy_from_slice(&bytes[..n]);
+    } else {
+        data.splice(start..payload_start, bytes[..n].iter().copied());
+    }
+
+    Ok(n as isize - (payload_start - start) as isize)
+}
+
+impl ProtoSink for Buffer {
+    fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn len_width(&self) -> LenWidth {
+        self.len_width
+    }
+
+    fn write_pos(&self) -> usize {
+        Buffer::write_pos(self)
+    }
+
+    fn try_write_bytes(&mut self, v: &[u8]) -> Result<(), EncodeError> {
+        self.write_slice_u8(v)
+    }
+
+    fn patch_len(&mut self, prefix: LenPrefix, len: usize) -> Result<(), EncodeError> {
+        let mut data = core::mem::take(&mut self.data);
+        let moved = patch_len_in(self, &mut data, 0, &prefix, len);
+        self.data = data;
+
+        // both cursors move with the bytes after the slot
+        let moved = moved?;
+
+        for pos in [&mut self.pos, &mut self.write_pos].iter_mut() {
+            if **pos >= prefix.payload_start {
+                **pos = (**pos as isize + moved) as usize;
+            }
+        }
+
+        Ok(())
+    }
+
+    // inserted or appended bytes are taken out again. Overwritten bytes can not be restored, so in
+    // Overwrite mode they are only dropped when the payload ran up to the end of the data
+    fn abort_len_prefix(&mut self, prefix: LenPrefix) {
+        let end = Buffer::write_pos(self);
+
+        let drop_end = match self.write_mode {
+            WriteMode::Overwrite if end < self.data.len() => prefix.start,
+            _ => end
+        };
+
+        self.data.drain(prefix.start..drop_end);
+        *self.write_cursor() = prefix.start;
+
+        if self.cursor == Cursor::Separate && self.pos > prefix.start {
+            self.pos = prefix.start.max(self.pos.saturating_sub(drop_end - prefix.start));
+        }
+    }
+}
+
+impl ProtoSource for Buffer {
+    fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn len_width(&self) -> LenWidth {
+        self.len_width
+    }
+
+    fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn available(&self) -> usize {
+        self.remaining()
+    }
+
+    fn try_read_exact(&mut self, len: usize) -> Result<&[u8], DecodeError> {
+        super::read_slice(&self.data, &mut self.pos, len)
+    }
+
+    fn check_remaining(&self, len: usize) -> Result<(), DecodeError> {
+        super::check_remaining(&self.data, self.pos, len)
+    }
+
+    fn try_read_varint(&mut self) -> Result<u64, DecodeError> {
+        super::read_varint(&self.data, &mut self.pos)
+    }
+}
+
+impl<'a> ProtoSource for BufferView<'a> {
+    fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn len_width(&self) -> LenWidth {
+        self.len_width
+    }
+
+    fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn available(&self) -> usize {
+        self.remaining()
+    }
+
+    fn try_read_exact(&mut self, len: usize) -> Result<&[u8], DecodeError> {
+        self.read_slice_u8(len)
+    }
+
+    fn check_remaining(&self, len: usize) -> Result<(), DecodeError> {
+        super::check_remaining(self.as_slice(), self.pos, len)
+    }
+
+    fn try_read_varint(&mut self) -> Result<u64, DecodeError> {
+        self.read_varint()
+    }
+}